@@ -0,0 +1,60 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Runs the command given in the command line every 2 seconds until it
+//! fails, printing its output only when it changes as a sorted list of
+//! lines, for instance:
+//!
+//! ```bash
+//! $ cargo run --features lib --example changes -- ls /tmp
+//! ```
+
+use color_eyre::Result;
+use tokio_stream::StreamExt;
+
+use ogle::Cmd;
+use ogle::Differ;
+use ogle::Duration;
+use ogle::EData;
+use ogle::EngineBuilder;
+use ogle::ExitConditions;
+use ogle::Mode;
+use ogle::SysReal;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let cmd = Cmd::from(std::env::args().skip(1).collect::<Vec<_>>());
+    let exit = ExitConditions {
+        failure: true,
+        ..Default::default()
+    };
+    let engine = EngineBuilder::new(cmd)
+        .with_period(Duration::seconds(2))
+        .with_exit(exit)
+        .build(SysReal::default())?;
+    let mut engine = Box::pin(engine);
+    let mut differ = Differ::new(Mode::Sorted);
+    while let Some(item) = engine.next().await {
+        match item.data {
+            EData::StartRun => differ.reset(),
+            EData::LineOut(line) => differ.push(line),
+            EData::Done(sts) => {
+                differ.finish();
+                if differ.has_changed() {
+                    println!("{}: changed", item.time);
+                    for line in &mut differ {
+                        println!("  {line}");
+                    }
+                }
+                if !sts.success() {
+                    println!("{}: exited with {sts}", item.time);
+                }
+            }
+            EData::Err(e) => println!("{}: error {e}", item.time),
+            _ => {}
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Prints the items that the engine yields for the first 3 runs of the
+//! command given in the command line, for instance:
+//!
+//! ```bash
+//! $ cargo run --features lib --example events -- sh -c 'date; sleep 1'
+//! ```
+
+use color_eyre::Result;
+use tokio_stream::StreamExt;
+
+use ogle::Cmd;
+use ogle::Duration;
+use ogle::EData;
+use ogle::EngineBuilder;
+use ogle::SysReal;
+
+const RUNS: usize = 3;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    color_eyre::install()?;
+    let cmd = Cmd::from(std::env::args().skip(1).collect::<Vec<_>>());
+    let engine = EngineBuilder::new(cmd)
+        .with_period(Duration::seconds(2))
+        .build(SysReal::default())?;
+    let mut engine = Box::pin(engine);
+    let mut done = 0;
+    while let Some(item) = engine.next().await {
+        let run = item.run.map(|run| run.to_string()).unwrap_or_default();
+        match item.data {
+            // Ticks are for animations, which we don't have:
+            EData::Tick => {}
+            EData::Done(sts) => {
+                println!("{} {run} exited with {sts}", item.time);
+                done += 1;
+                if done == RUNS {
+                    break;
+                }
+            }
+            data => println!("{} {run} {data:?}", item.time),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Alerts on thresholds of the tracked value.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use crate::process_wrapper::Cmd;
+
+static CONDITION: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^\s*(\w+)\s*(<=|>=|==|!=|<|>)\s*(-?\d+(?:\.\d+)?)\s*$")
+        .expect("invalid alert regex")
+});
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Eq => "==",
+            Op::Ne => "!=",
+        };
+        write!(f, "{op}")
+    }
+}
+
+/// A threshold on the tracked value, and what we do when the value
+/// crosses it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    /// Name of the tracked value, which is the name of the capture
+    /// group of the tracking regex.
+    pub name: String,
+    op: Op,
+    threshold: f64,
+    /// If the alert is currently raised.
+    active: bool,
+    /// Ring the terminal bell when the alert is raised.
+    pub bell: bool,
+    /// Shell command executed when the alert is raised or cleared,
+    /// with the new state and the value as arguments.
+    pub hook: Option<String>,
+    /// Exit when the alert is raised.
+    pub exit: bool,
+}
+
+impl Alert {
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn condition(&self, value: f64) -> bool {
+        match self.op {
+            Op::Lt => value < self.threshold,
+            Op::Le => value <= self.threshold,
+            Op::Gt => value > self.threshold,
+            Op::Ge => value >= self.threshold,
+            Op::Eq => value == self.threshold,
+            Op::Ne => value != self.threshold,
+        }
+    }
+
+    /// Evaluates the value found in a run, returning the new state of
+    /// the alert if it has changed.
+    pub fn evaluate(&mut self, value: f64) -> Option<bool> {
+        let active = self.condition(value);
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+        Some(active)
+    }
+
+    /// Returns the command that runs the hook for the current state.
+    pub fn hook_cmd(&self, value: f64) -> Option<Cmd> {
+        let hook = self.hook.as_ref()?;
+        let state = if self.active { "raised" } else { "cleared" };
+        let args = ["sh", "-c", hook, "ogle", state, &value.to_string()];
+        Some(Cmd::from(&args[..]))
+    }
+}
+
+impl FromStr for Alert {
+    type Err = color_eyre::Report;
+
+    /// Parses conditions like `free_mb < 500`.
+    fn from_str(s: &str) -> Result<Self> {
+        let captures = CONDITION.captures(s).ok_or_else(|| {
+            eyre!("invalid alert condition {s}, expected something like 'name < 500'")
+        })?;
+        let op = match &captures[2] {
+            "<" => Op::Lt,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            ">=" => Op::Ge,
+            "==" => Op::Eq,
+            _ => Op::Ne,
+        };
+        Ok(Alert {
+            name: captures[1].to_owned(),
+            op,
+            threshold: captures[3].parse()?,
+            active: false,
+            bell: false,
+            hook: None,
+            exit: false,
+        })
+    }
+}
+
+impl fmt::Display for Alert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.name, self.op, self.threshold)
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let alert = Alert::from_str("free_mb < 500")?;
+        assert_eq!(alert.name, "free_mb");
+        assert_eq!(alert.to_string(), "free_mb < 500");
+        assert_eq!(Alert::from_str("x>=-1.5")?.to_string(), "x >= -1.5");
+        assert!(Alert::from_str("free_mb <").is_err());
+        assert!(Alert::from_str("free mb < 5").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_evaluate() -> Result<()> {
+        let mut alert = Alert::from_str("free_mb < 500")?;
+        assert_eq!(alert.evaluate(600.0), None);
+        assert_eq!(alert.evaluate(499.0), Some(true));
+        assert!(alert.is_active());
+        assert_eq!(alert.evaluate(400.0), None);
+        assert_eq!(alert.evaluate(500.0), Some(false));
+        assert!(!alert.is_active());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hook_cmd() -> Result<()> {
+        let mut alert = Alert::from_str("x > 1")?;
+        assert!(alert.hook_cmd(2.0).is_none());
+        alert.hook = Some("notify".to_owned());
+        alert.evaluate(2.0);
+        assert_eq!(
+            alert.hook_cmd(2.0).map(|cmd| cmd.to_string()),
+            Some("sh -c notify ogle raised 2".to_owned())
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,104 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Recording of the rendered output in the [asciicast v2] format, which
+//! can be played by asciinema and other standard players.
+//!
+//! [asciicast v2]: https://docs.asciinema.org/manual/asciicast/v2/
+
+use serde_json::json;
+use std::io;
+use std::path::PathBuf;
+
+use crate::output::OutputCommand;
+use crate::output::OutputCommandTrait;
+use crate::process_wrapper::Cmd;
+use crate::sys::SysApi;
+use crate::time_wrapper::Instant;
+
+/// Width of the terminal in the header, if we can't get it.
+const WIDTH: u16 = 80;
+
+/// Height of the terminal in the header; we only ever move the cursor
+/// up by one line, so any height works.
+const HEIGHT: u16 = 24;
+
+/// Appends the output commands to an asciicast file.
+#[derive(Debug)]
+pub struct Asciicast {
+    path: PathBuf,
+    /// The time of the header, which the event times are relative to.
+    start: Instant,
+}
+
+impl Asciicast {
+    /// Creates the asciicast file, writing the header.
+    pub fn new<SI: SysApi>(sys: &mut SI, path: PathBuf, cmd: &Cmd) -> io::Result<Self> {
+        let start = sys.now();
+        let header = json!({
+            "version": 2,
+            "width": sys.get_width().unwrap_or(WIDTH),
+            "height": HEIGHT,
+            "timestamp": (&start - &Instant::default()).num_seconds(),
+            "title": format!("ogle {cmd}"),
+        });
+        sys.write_file(&path, &format!("{header}\n"))?;
+        Ok(Self { path, start })
+    }
+
+    /// Appends the bytes that `command` writes as an output event.
+    pub fn record<SI: SysApi>(&self, sys: &mut SI, command: &OutputCommand) -> io::Result<()> {
+        let elapsed = &sys.now() - &self.start;
+        let data = String::from_utf8_lossy(&command.ansi()).into_owned();
+        let event = json!([elapsed.num_milliseconds() as f64 / 1000.0, "o", data]);
+        sys.append_file(&self.path, &format!("{event}\n"))
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::output::ClearLine;
+    use crate::output::SetColor;
+    use crate::output::WriteAll;
+    use crate::sys::SysVirtual;
+    use crate::term_wrapper::Color;
+    use color_eyre::Result;
+    use serde_json::Value;
+    use std::path::Path;
+
+    #[test]
+    fn test_record() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let path = Path::new("demo.cast");
+        let cmd = Cmd::from(&["echo", "a"][..]);
+        let asciicast = Asciicast::new(&mut sys, path.to_owned(), &cmd)?;
+        for command in [
+            OutputCommand::SetColor(SetColor(Some(Color::Red))),
+            OutputCommand::WriteAll(WriteAll(b"a\n".to_vec())),
+            OutputCommand::SetColor(SetColor(None)),
+            OutputCommand::ClearLine(ClearLine {}),
+        ] {
+            asciicast.record(&mut sys, &command)?;
+        }
+        let contents = sys.read_file(path)?;
+        let lines = contents
+            .lines()
+            .map(serde_json::from_str::<Value>)
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            lines,
+            vec![
+                json!({"version": 2, "width": 80, "height": 24, "timestamp": 0, "title": "ogle echo a"}),
+                json!([1.0, "o", "\x1b[38;5;1m"]),
+                json!([2.0, "o", "a\n\x1b[1G"]),
+                json!([3.0, "o", "\x1b[0m"]),
+                json!([4.0, "o", "\x1b[2K\x1b[1G"]),
+            ]
+        );
+        Ok(())
+    }
+}
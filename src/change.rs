@@ -0,0 +1,142 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Change policy: which facets of a run count as a change.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cli::ChangeOn;
+use crate::process_wrapper::ExitSts;
+use crate::time_wrapper::Duration;
+
+/// Number of previous durations we use to compute the median.
+const DURATIONS_WINDOW: usize = 100;
+
+impl fmt::Display for ChangeOn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeOn::Output => write!(f, "output"),
+            ChangeOn::Exit => write!(f, "exit status"),
+            ChangeOn::Duration => write!(f, "duration"),
+        }
+    }
+}
+
+/// Decides if a finished run has changed, based on the facets we
+/// were configured with.
+#[derive(Debug, Default)]
+pub struct ChangePolicy {
+    facets: Vec<ChangeOn>,
+    last_exit: Option<ExitSts>,
+    durations: VecDeque<Duration>,
+}
+
+impl ChangePolicy {
+    pub fn new(facets: &[ChangeOn]) -> Self {
+        Self {
+            facets: facets.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns true if changes in the output count.
+    pub fn output(&self) -> bool {
+        self.facets.contains(&ChangeOn::Output)
+    }
+
+    /// Evaluates the run that just finished, returning the facets
+    /// that have changed.
+    pub fn evaluate(
+        &mut self,
+        output_changed: bool,
+        sts: &ExitSts,
+        duration: Duration,
+    ) -> Vec<ChangeOn> {
+        let exit_changed = self.last_exit.as_ref().is_some_and(|last| last != sts);
+        let duration_changed = self
+            .median()
+            .is_some_and(|median| duration.num_milliseconds() > 2 * median);
+        self.last_exit = Some(sts.clone());
+        if self.durations.len() == DURATIONS_WINDOW {
+            self.durations.pop_front();
+        }
+        self.durations.push_back(duration);
+        self.facets
+            .iter()
+            .copied()
+            .filter(|facet| match facet {
+                ChangeOn::Output => output_changed,
+                ChangeOn::Exit => exit_changed,
+                ChangeOn::Duration => duration_changed,
+            })
+            .collect()
+    }
+
+    /// Median of the previous durations, in milliseconds.
+    fn median(&self) -> Option<i64> {
+        let mut millis = self
+            .durations
+            .iter()
+            .map(|d| d.num_milliseconds())
+            .collect::<Vec<_>>();
+        millis.sort_unstable();
+        let len = millis.len();
+        match len {
+            0 => None,
+            _ if len % 2 == 1 => Some(millis[len / 2]),
+            _ => Some((millis[len / 2 - 1] + millis[len / 2]) / 2),
+        }
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_output_and_exit() {
+        let mut policy = ChangePolicy::new(&[ChangeOn::Output, ChangeOn::Exit]);
+        assert!(policy.output());
+        let secs = Duration::seconds(1);
+        assert_eq!(
+            policy.evaluate(true, &ExitSts::Success, secs),
+            vec![ChangeOn::Output]
+        );
+        assert_eq!(policy.evaluate(false, &ExitSts::Success, secs), vec![]);
+        assert_eq!(
+            policy.evaluate(true, &ExitSts::Code(1), secs),
+            vec![ChangeOn::Output, ChangeOn::Exit]
+        );
+        assert_eq!(
+            policy.evaluate(false, &ExitSts::Success, secs),
+            vec![ChangeOn::Exit]
+        );
+    }
+
+    #[test]
+    fn test_duration() {
+        let mut policy = ChangePolicy::new(&[ChangeOn::Duration]);
+        assert!(!policy.output());
+        let sts = ExitSts::Success;
+        assert_eq!(policy.evaluate(true, &sts, Duration::seconds(1)), vec![]);
+        assert_eq!(
+            policy.evaluate(false, &sts, Duration::seconds(3)),
+            vec![ChangeOn::Duration]
+        );
+        // Median is now 2s:
+        assert_eq!(policy.evaluate(false, &sts, Duration::seconds(4)), vec![]);
+        assert_eq!(
+            policy.evaluate(false, &sts, Duration::seconds(7)),
+            vec![ChangeOn::Duration]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(format!("{}", ChangeOn::Exit), "exit status");
+    }
+}
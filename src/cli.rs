@@ -9,9 +9,92 @@
 // [`clap`]: https://docs.rs/clap/latest/clap/
 
 use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+use std::path::PathBuf;
+
+/// Output streams of the command that are compared between runs.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Compare {
+    /// Compare only stdout; stderr is shown only when stdout changes
+    Stdout,
+    /// Compare only stderr; stdout is shown only when stderr changes
+    Stderr,
+    /// Compare both stdout and stderr
+    #[default]
+    Both,
+}
+
+/// Facets of a run that can count as a change.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeOn {
+    /// The output of the command
+    Output,
+    /// The exit status of the command
+    Exit,
+    /// The duration of the run, if it took more than twice the median
+    /// of the previous runs
+    Duration,
+}
+
+/// The run that the other runs are compared against.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Baseline {
+    /// Compare each run against the previous one
+    #[default]
+    Previous,
+    /// Compare each run against the first one
+    First,
+}
+
+/// How the lines of the command are timestamped.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineTimestamps {
+    /// Time when the line arrived
+    Absolute,
+    /// Time since the start of the run
+    Relative,
+}
+
+/// Format of the output of the command.
+#[derive(ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Lines of text
+    #[default]
+    Lines,
+    /// A JSON document, compared structurally
+    Json,
+    /// A whitespace-aligned table with a header, compared row by row
+    Table,
+}
+
+/// Format of the summary printed when ogle exits.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SummaryFormat {
+    /// Human-readable lines
+    Text,
+    /// A single-line JSON object, for scripts
+    Json,
+}
+
+/// Subcommands, which replace the command to run.
+#[derive(Subcommand, Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Replay a session recorded with --record, showing it with the
+    /// current options
+    Replay {
+        /// The recording
+        file: PathBuf,
+
+        /// Speed factor of the replay; 0 shows it without waiting
+        #[arg(long, default_value = "1")]
+        speed: f64,
+    },
+}
 
 #[derive(Parser, Debug)]
 #[command(
+    subcommand_negates_reqs = true,
     author,
     version,
     about,
@@ -22,6 +105,183 @@ pub struct Cli {
     #[arg(short, long, default_value = "1")]
     pub period: u32,
 
+    /// Maximum number of runs in flight; if greater than 1, a new run
+    /// starts every period even if the previous one is still going
+    #[arg(long = "max-concurrent", default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_concurrent: u32,
+
+    /// Output streams compared to detect changes
+    #[arg(long, value_enum, default_value_t = Compare::Both)]
+    pub compare: Compare,
+
+    /// Facets of the runs that count as changes, separated by commas
+    #[arg(
+        long = "change-on",
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [ChangeOn::Output, ChangeOn::Exit]
+    )]
+    pub change_on: Vec<ChangeOn>,
+
+    /// Compare the runs as sets of lines, showing only the added and
+    /// removed lines
+    #[arg(long, conflicts_with = "sort")]
+    pub unordered: bool,
+
+    /// Sort the lines of each run before comparing and showing them
+    #[arg(long)]
+    pub sort: bool,
+
+    /// Consider the numbers in corresponding lines equal if they differ
+    /// by at most TOLERANCE, either absolute (0.5) or relative (5%)
+    #[arg(long, value_name = "TOLERANCE", conflicts_with = "unordered")]
+    pub tolerance: Option<String>,
+
+    /// Annotate the numbers in the output with their change since the
+    /// previous run and the corresponding rate per second
+    #[arg(long, conflicts_with_all = ["unordered", "sort"])]
+    pub delta: bool,
+
+    /// Track the number captured by the single group of REGEX in the
+    /// output, showing its recent values in the status line
+    #[arg(long, value_name = "REGEX")]
+    pub track: Option<String>,
+
+    /// Alert when the value tracked by --track crosses a threshold,
+    /// e.g. 'free_mb < 500', where free_mb is the name of the capture
+    /// group
+    #[arg(long, value_name = "CONDITION", requires = "track")]
+    pub alert: Option<String>,
+
+    /// Ring the terminal bell when the alert is raised
+    #[arg(long = "alert-bell", requires = "alert")]
+    pub alert_bell: bool,
+
+    /// Run CMD with sh when the alert is raised or cleared; the
+    /// arguments are the new state and the value
+    #[arg(long = "alert-hook", value_name = "CMD", requires = "alert")]
+    pub alert_hook: Option<String>,
+
+    /// Exit when the alert is raised
+    #[arg(long = "alert-exit", requires = "alert")]
+    pub alert_exit: bool,
+
+    /// Format of the output of the command, which defines how runs are
+    /// compared
+    #[arg(long, value_enum, default_value_t = Format::Lines)]
+    pub format: Format,
+
+    /// With --format json, compare only the values selected by PATH,
+    /// e.g. .items[].status.phase
+    #[arg(long, value_name = "PATH")]
+    pub select: Option<String>,
+
+    /// With --format table, identify the rows by the values of the
+    /// column NAME; defaults to the first column
+    #[arg(long = "key-column", value_name = "NAME")]
+    pub key_column: Option<String>,
+
+    /// Pipe the stdout of each run through CMD, run with sh, before
+    /// comparing and showing it
+    #[arg(long, value_name = "CMD")]
+    pub filter: Option<String>,
+
+    /// Prefix each line with the time it arrived; not used when
+    /// comparing runs
+    #[arg(
+        long = "line-timestamps",
+        value_enum,
+        conflicts_with_all = ["unordered", "sort"]
+    )]
+    pub line_timestamps: Option<LineTimestamps>,
+
+    /// Format of the timestamps: a strftime pattern, like %H:%M:%S, or
+    /// one of the presets iso, time, relative (to the start of ogle)
+    /// and none
+    #[arg(
+        long = "time-format",
+        value_name = "FORMAT",
+        default_value = "%Y-%m-%d %H:%M:%S"
+    )]
+    pub time_format: String,
+
+    /// Show the timestamps in UTC
+    #[arg(long, conflicts_with = "local")]
+    pub utc: bool,
+
+    /// Show the timestamps in the local timezone, which is the default
+    #[arg(long)]
+    pub local: bool,
+
+    /// Template of the prefix of the lines that ogle prints, with the
+    /// {time} placeholder; use an empty prefix to strip it
+    #[arg(long, value_name = "TEMPLATE", default_value = "<O> {time}")]
+    pub prefix: String,
+
+    /// Template of the status line, with the placeholders {state},
+    /// {runs}, {unchanged}, {exit}, {bar}, {spinner}, {inflight} and
+    /// {tracked}
+    #[arg(long, value_name = "TEMPLATE")]
+    pub status: Option<String>,
+
+    /// Print a summary of the runs when exiting
+    #[arg(
+        long,
+        value_enum,
+        value_name = "FORMAT",
+        num_args = 0..=1,
+        default_missing_value = "text"
+    )]
+    pub summary: Option<SummaryFormat>,
+
+    /// Store the output and metadata of each changed run in DIR
+    #[arg(long = "history-dir", value_name = "DIR")]
+    pub history_dir: Option<PathBuf>,
+
+    /// Keep only the last N runs in the --history-dir
+    #[arg(
+        long = "history-max-runs",
+        value_name = "N",
+        requires = "history_dir",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub history_max_runs: Option<u32>,
+
+    /// Remove the runs older than SECONDS from the --history-dir
+    #[arg(
+        long = "history-max-age",
+        value_name = "SECONDS",
+        requires = "history_dir"
+    )]
+    pub history_max_age: Option<u32>,
+
+    /// Save the output of the last run and the counters in FILE on
+    /// each change, and resume from them when starting
+    #[arg(long = "state-file", value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Record the session in FILE, to be shown later with the replay
+    /// subcommand
+    #[arg(long, value_name = "FILE")]
+    pub record: Option<PathBuf>,
+
+    /// Record the output in FILE in the asciicast v2 format, which can
+    /// be played by asciinema
+    #[arg(long, value_name = "FILE")]
+    pub asciicast: Option<PathBuf>,
+
+    /// Run that the other runs are compared against
+    #[arg(long, value_enum, default_value_t = Baseline::Previous)]
+    pub baseline: Baseline,
+
+    /// Compare the stdout of each run against the contents of FILE
+    #[arg(long, value_name = "FILE", conflicts_with = "baseline")]
+    pub expect: Option<PathBuf>,
+
+    /// Loop until the output matches the contents of the --expect FILE
+    #[arg(long = "until-expected", requires = "expect")]
+    pub until_expected: bool,
+
     /// Loop until the command exits with success
     #[arg(short = 'z', long = "until-success")]
     pub until_success: bool,
@@ -30,9 +290,24 @@ pub struct Cli {
     #[arg(short = 'e', long = "until-failure")]
     pub until_failure: bool,
 
+    /// Loop until the output doesn't change for N consecutive runs
+    #[arg(long = "until-stable", value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    pub until_stable: Option<u32>,
+
+    /// Loop until a line of the output matches REGEX
+    #[arg(long = "until-match", value_name = "REGEX")]
+    pub until_match: Option<String>,
+
+    /// Loop until no line of the output matches REGEX
+    #[arg(long = "until-no-match", value_name = "REGEX")]
+    pub until_no_match: Option<String>,
+
     /// The command to run
     #[arg(value_parser, required = true)]
     pub command: Vec<String>,
+
+    #[command(subcommand)]
+    pub action: Option<Action>,
 }
 
 #[cfg(test)]
@@ -82,6 +357,285 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn max_concurrent() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.max_concurrent, 1);
+        let cli = Cli::try_parse_from(vec!["ogle", "--max-concurrent", "3", "--", "true"])?;
+        assert_eq!(cli.max_concurrent, 3);
+        let cli = Cli::try_parse_from(vec!["ogle", "--max-concurrent", "0", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn compare() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.compare, Compare::Both);
+        let cli = Cli::try_parse_from(vec!["ogle", "--compare", "stdout", "--", "true"])?;
+        assert_eq!(cli.compare, Compare::Stdout);
+        let cli = Cli::try_parse_from(vec!["ogle", "--compare", "stderr", "--", "true"])?;
+        assert_eq!(cli.compare, Compare::Stderr);
+        let cli = Cli::try_parse_from(vec!["ogle", "--compare", "none", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn change_on() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.change_on, vec![ChangeOn::Output, ChangeOn::Exit]);
+        let cli = Cli::try_parse_from(vec!["ogle", "--change-on", "exit,duration", "--", "true"])?;
+        assert_eq!(cli.change_on, vec![ChangeOn::Exit, ChangeOn::Duration]);
+        let cli = Cli::try_parse_from(vec!["ogle", "--change-on", "size", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn baseline() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.baseline, Baseline::Previous);
+        assert_eq!(cli.expect, None);
+        let cli = Cli::try_parse_from(vec!["ogle", "--baseline", "first", "--", "true"])?;
+        assert_eq!(cli.baseline, Baseline::First);
+        let cli = Cli::try_parse_from(vec!["ogle", "--expect", "f", "--until-expected", "true"])?;
+        assert_eq!(cli.expect, Some(PathBuf::from("f")));
+        assert!(cli.until_expected);
+        let cli = Cli::try_parse_from(vec!["ogle", "--until-expected", "true"]);
+        assert!(cli.is_err(), "--until-expected requires --expect");
+        let cli = Cli::try_parse_from(vec!["ogle", "--baseline", "first", "--expect", "f", "true"]);
+        assert!(cli.is_err(), "--baseline conflicts with --expect");
+        Ok(())
+    }
+
+    #[test]
+    fn until_match() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--until-match", "^ok", "--", "true"])?;
+        assert_eq!(cli.until_match.as_deref(), Some("^ok"));
+        assert_eq!(cli.until_no_match, None);
+        let cli = Cli::try_parse_from(vec!["ogle", "--until-no-match", "err", "--", "true"])?;
+        assert_eq!(cli.until_no_match.as_deref(), Some("err"));
+        Ok(())
+    }
+
+    #[test]
+    fn until_stable() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--until-stable", "5", "--", "true"])?;
+        assert_eq!(cli.until_stable, Some(5));
+        let cli = Cli::try_parse_from(vec!["ogle", "--until-stable", "0", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn unordered_sort() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert!(!cli.unordered);
+        assert!(!cli.sort);
+        let cli = Cli::try_parse_from(vec!["ogle", "--unordered", "--", "true"])?;
+        assert!(cli.unordered);
+        let cli = Cli::try_parse_from(vec!["ogle", "--sort", "--", "true"])?;
+        assert!(cli.sort);
+        let cli = Cli::try_parse_from(vec!["ogle", "--sort", "--unordered", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn tolerance() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--tolerance", "5%", "--", "true"])?;
+        assert_eq!(cli.tolerance.as_deref(), Some("5%"));
+        let cli = Cli::try_parse_from(vec![
+            "ogle",
+            "--tolerance",
+            "1",
+            "--unordered",
+            "--",
+            "true",
+        ]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn delta() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--delta", "--", "true"])?;
+        assert!(cli.delta);
+        let cli = Cli::try_parse_from(vec!["ogle", "--delta", "--sort", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn track() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--track", r"depth (\d+)", "--", "true"])?;
+        assert_eq!(cli.track.as_deref(), Some(r"depth (\d+)"));
+        Ok(())
+    }
+
+    #[test]
+    fn alert() -> Result<()> {
+        let cli = Cli::try_parse_from(vec![
+            "ogle",
+            "--track",
+            r"free: (?<free_mb>\d+)",
+            "--alert",
+            "free_mb < 500",
+            "--alert-exit",
+            "--",
+            "true",
+        ])?;
+        assert_eq!(cli.alert.as_deref(), Some("free_mb < 500"));
+        assert!(cli.alert_exit);
+        let cli = Cli::try_parse_from(vec!["ogle", "--alert", "x < 5", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn format() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.format, Format::Lines);
+        let cli = Cli::try_parse_from(vec![
+            "ogle", "--format", "json", "--select", ".a[]", "--", "true",
+        ])?;
+        assert_eq!(cli.format, Format::Json);
+        assert_eq!(cli.select.as_deref(), Some(".a[]"));
+        let cli = Cli::try_parse_from(vec![
+            "ogle",
+            "--format",
+            "table",
+            "--key-column",
+            "NAME",
+            "--",
+            "true",
+        ])?;
+        assert_eq!(cli.format, Format::Table);
+        assert_eq!(cli.key_column.as_deref(), Some("NAME"));
+        Ok(())
+    }
+
+    #[test]
+    fn filter() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--filter", "grep -v x", "--", "true"])?;
+        assert_eq!(cli.filter.as_deref(), Some("grep -v x"));
+        Ok(())
+    }
+
+    #[test]
+    fn line_timestamps() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.line_timestamps, None);
+        let cli = Cli::try_parse_from(vec!["ogle", "--line-timestamps", "relative", "--", "true"])?;
+        assert_eq!(cli.line_timestamps, Some(LineTimestamps::Relative));
+        Ok(())
+    }
+
+    #[test]
+    fn templates() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.prefix, "<O> {time}");
+        assert_eq!(cli.status, None);
+        let cli = Cli::try_parse_from(vec![
+            "ogle",
+            "--prefix",
+            "",
+            "--status",
+            "{state} {bar}",
+            "--",
+            "true",
+        ])?;
+        assert_eq!(cli.prefix, "");
+        assert_eq!(cli.status.as_deref(), Some("{state} {bar}"));
+        Ok(())
+    }
+
+    #[test]
+    fn summary() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.summary, None);
+        let cli = Cli::try_parse_from(vec!["ogle", "--summary", "--", "true"])?;
+        assert_eq!(cli.summary, Some(SummaryFormat::Text));
+        let cli = Cli::try_parse_from(vec!["ogle", "--summary", "json", "--", "true"])?;
+        assert_eq!(cli.summary, Some(SummaryFormat::Json));
+        Ok(())
+    }
+
+    #[test]
+    fn history() -> Result<()> {
+        let cli = Cli::try_parse_from(vec![
+            "ogle",
+            "--history-dir",
+            "hist",
+            "--history-max-runs",
+            "10",
+            "--history-max-age",
+            "3600",
+            "--",
+            "true",
+        ])?;
+        assert_eq!(cli.history_dir, Some(PathBuf::from("hist")));
+        assert_eq!(cli.history_max_runs, Some(10));
+        assert_eq!(cli.history_max_age, Some(3600));
+        let cli = Cli::try_parse_from(vec!["ogle", "--history-max-runs", "1", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn state_file() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--state-file", "s.json", "--", "true"])?;
+        assert_eq!(cli.state_file, Some(PathBuf::from("s.json")));
+        Ok(())
+    }
+
+    #[test]
+    fn asciicast() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--asciicast", "demo.cast", "--", "true"])?;
+        assert_eq!(cli.asciicast, Some(PathBuf::from("demo.cast")));
+        Ok(())
+    }
+
+    #[test]
+    fn record_replay() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--record", "s.ogle", "--", "true"])?;
+        assert_eq!(cli.record, Some(PathBuf::from("s.ogle")));
+        assert_eq!(cli.action, None);
+        let cli = Cli::try_parse_from(vec!["ogle", "--utc", "replay", "s.ogle"])?;
+        assert!(cli.command.is_empty());
+        assert_eq!(
+            cli.action,
+            Some(Action::Replay {
+                file: PathBuf::from("s.ogle"),
+                speed: 1.0
+            })
+        );
+        let cli = Cli::try_parse_from(vec!["ogle", "replay", "--speed", "0", "s.ogle"])?;
+        assert!(matches!(
+            cli.action,
+            Some(Action::Replay { speed: 0.0, .. })
+        ));
+        // After --, replay is the command to run:
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "replay", "s.ogle"])?;
+        assert_eq!(cli.command, vec!["replay", "s.ogle"]);
+        assert_eq!(cli.action, None);
+        Ok(())
+    }
+
+    #[test]
+    fn time_format() -> Result<()> {
+        let cli = Cli::try_parse_from(vec!["ogle", "--", "true"])?;
+        assert_eq!(cli.time_format, "%Y-%m-%d %H:%M:%S");
+        assert!(!cli.utc);
+        let cli = Cli::try_parse_from(vec!["ogle", "--time-format", "iso", "--utc", "--", "true"])?;
+        assert_eq!(cli.time_format, "iso");
+        assert!(cli.utc);
+        let cli = Cli::try_parse_from(vec!["ogle", "--utc", "--local", "--", "true"]);
+        assert!(cli.is_err());
+        Ok(())
+    }
+
     async fn run_cmd(cmd: Vec<&str>) -> Result<ExitStatus> {
         let cli = Cli::try_parse_from(cmd)?;
         let cmd = Cmd::from(cli.command.clone());
@@ -2,62 +2,307 @@
 // This file is subject to the terms and conditions defined in
 // file 'LICENSE', which is part of this source code package.
 
-use std::collections::VecDeque;
+use std::collections::HashMap;
 use tracing::instrument;
 
+use crate::json;
+use crate::json::Selector;
+use crate::numeric::Tolerance;
+use crate::table;
+
+/// How the lines of the runs are compared.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Line by line, in order; lines are yielded as soon as a change
+    /// is detected.
+    #[default]
+    Lines,
+    /// Line by line, after sorting them; the sorted lines are yielded
+    /// when the run finishes.
+    Sorted,
+    /// As multisets of lines; only the added and removed lines are
+    /// yielded, when the run finishes.
+    Unordered,
+    /// As JSON documents, compared structurally; the changed, added
+    /// and removed paths are yielded when the run finishes.
+    Json,
+    /// As tables with a header, compared row by row; the changed,
+    /// added and removed rows are yielded when the run finishes.
+    Table,
+}
+
+/// Compares the lines of each run against the reference, usually the
+/// lines of the previous run, and yields the lines that should be
+/// shown.
+///
+/// The lines of a run are provided with [`Differ::push`], and the end
+/// of the run is signaled with [`Differ::finish`]; iterating over a
+/// changed `&mut Differ` yields the lines.
 #[derive(Debug, Default)]
 pub struct Differ {
+    mode: Mode,
+    /// Lines we compare the current run against, usually the ones from
+    /// the previous run.
+    reference: Vec<String>,
+    /// Lines of the current run.
+    current: Vec<String>,
+    /// If set, the reference is not replaced by the current run on
+    /// reset.
+    pinned: bool,
+    /// If set, lines whose numbers are within the tolerance are
+    /// considered equal; not used in [`Mode::Unordered`].
+    tolerance: Option<Tolerance>,
+    /// Reduces the JSON documents before comparing, in [`Mode::Json`].
+    selector: Option<Selector>,
+    /// Column that identifies the rows, in [`Mode::Table`].
+    key_column: Option<String>,
+    /// Problem found when comparing the current run, like a missing
+    /// key column; each kind is reported only once.
+    warning: Option<String>,
+    /// If we have already reported a missing key column.
+    warned_key: bool,
+    /// If the current run is different from the reference.
     changed: bool,
-    lines: VecDeque<String>,
+    /// If the lines of the current run should be yielded even if
+    /// they have not changed.
+    shown: bool,
+    /// If the current run has finished.
+    finished: bool,
+    /// Added and removed lines, in [`Mode::Unordered`], or changed
+    /// paths, in [`Mode::Json`], or changed rows, in [`Mode::Table`].
+    diff: Vec<String>,
+    /// Index of the next line to yield.
     iline: usize,
 }
 
 impl Differ {
+    /// Creates a differ that compares each run against the previous
+    /// one.
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a differ that compares all runs against the provided
+    /// lines.
+    pub fn pinned(mode: Mode, mut reference: Vec<String>) -> Self {
+        if mode == Mode::Sorted {
+            reference.sort();
+        }
+        Self {
+            mode,
+            reference,
+            pinned: true,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a differ that compares the first run against the
+    /// provided lines, as if they were the output of a previous run.
+    pub fn resumed(mode: Mode, reference: Vec<String>) -> Self {
+        Self {
+            mode,
+            current: reference.clone(),
+            reference,
+            ..Default::default()
+        }
+    }
+
+    /// Sets the tolerance used when comparing the numbers in the
+    /// lines.
+    pub fn with_tolerance(mut self, tolerance: Option<Tolerance>) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Sets the selector applied to the JSON documents.
+    pub fn with_selector(mut self, selector: Option<Selector>) -> Self {
+        self.selector = selector;
+        self
+    }
+
+    /// Sets the column that identifies the rows of tables.
+    pub fn with_key_column(mut self, key_column: Option<String>) -> Self {
+        self.key_column = key_column;
+        self
+    }
+
+    /// Signals the start of a new run.
     #[instrument(level = "debug")]
     pub fn reset(&mut self) {
+        if self.keeps_reference() {
+            self.current.clear();
+        } else {
+            self.reference = std::mem::take(&mut self.current);
+        }
         self.changed = false;
+        self.shown = false;
+        self.finished = false;
+        self.diff.clear();
         self.iline = 0;
     }
 
+    /// Adds a line to the current run.
     #[instrument(level = "debug", skip(self), fields(iline=self.iline, line=line))]
     pub fn push(&mut self, line: String) {
-        if self.changed {
-            // If we are in "changed" mode, we just append the lines:
-            self.lines.push_back(line);
+        if self.mode == Mode::Lines
+            && !self.changed
+            && !(self.reference.get(self.current.len())).is_some_and(|r| self.same(r, &line))
+        {
+            // New or different line.
+            self.changed = true;
+        }
+        self.current.push(line);
+    }
+
+    /// Returns true if the next run is compared against the current
+    /// reference instead of the current run.
+    fn keeps_reference(&self) -> bool {
+        // With a tolerance, we keep the reference of unchanged runs so
+        // that small variations don't accumulate unnoticed.
+        self.pinned || (self.tolerance.is_some() && !self.changed)
+    }
+
+    /// Returns the lines that the next run is compared against.
+    pub fn next_reference(&self) -> &[String] {
+        if self.keeps_reference() {
+            &self.reference
         } else {
-            // Otherwise, we check
-            if self.iline >= self.lines.len() || self.lines.get(self.iline) != Some(&line) {
-                // New or different line.
-                self.changed = true;
-                self.lines.truncate(self.iline);
-                self.lines.push_back(line);
-                // self.iline is now the read position, which starts at 0:
-                self.iline = 0;
-            } else {
-                // Same line, get ready for the next one:
-                self.iline += 1;
+            &self.current
+        }
+    }
+
+    /// Makes the iterator yield the lines of the current run even if
+    /// it has not changed.
+    #[instrument(level = "debug", skip(self), fields(changed=self.changed))]
+    pub fn show(&mut self) {
+        self.shown = true;
+    }
+
+    /// Signals the end of the current run, which is when we compare
+    /// the whole run in the modes other than [`Mode::Lines`].
+    #[instrument(level = "debug", skip(self), fields(changed=self.changed))]
+    pub fn finish(&mut self) {
+        self.finished = true;
+        match self.mode {
+            Mode::Lines => {
+                if self.current.len() < self.reference.len() {
+                    self.changed = true;
+                }
+            }
+            Mode::Sorted => {
+                self.current.sort();
+                self.changed = self.current.len() != self.reference.len()
+                    || !(self.current.iter())
+                        .zip(self.reference.iter())
+                        .all(|(c, r)| self.same(r, c));
+            }
+            Mode::Unordered => {
+                let (removed, added) = multiset_diff(&self.reference, &self.current);
+                self.diff = removed
+                    .into_iter()
+                    .map(|line| format!("- {line}"))
+                    .chain(added.into_iter().map(|line| format!("+ {line}")))
+                    .collect();
+                self.changed = !self.diff.is_empty();
+            }
+            Mode::Json => {
+                let selector = self.selector.as_ref();
+                let reference = json::leaves(&json::parse(&self.reference), selector);
+                let current = json::leaves(&json::parse(&self.current), selector);
+                self.diff = json::diff(&reference, &current);
+                self.changed = !self.diff.is_empty();
+            }
+            Mode::Table => {
+                let key_column = self.key_column.as_deref();
+                let (diff, warning) = table::diff(&self.reference, &self.current, key_column);
+                self.diff = diff;
+                if warning.is_some() && !self.warned_key {
+                    self.warned_key = true;
+                    self.warning = warning;
+                }
+                self.changed = !self.diff.is_empty();
             }
         }
     }
 
+    /// Pins the lines of the current run as the reference for all the
+    /// next runs.
+    #[instrument(level = "debug", skip(self), fields(changed=self.changed))]
+    pub fn pin(&mut self) {
+        self.reference = self.current.clone();
+        self.pinned = true;
+    }
+
+    /// Returns true if the lines are considered equal.
+    fn same(&self, a: &str, b: &str) -> bool {
+        match self.tolerance {
+            Some(tolerance) => tolerance.matches(a, b),
+            None => a == b,
+        }
+    }
+
+    /// Returns true if the current run is different from the
+    /// reference.
     #[instrument(level = "debug", skip(self), fields(changed=self.changed))]
     pub fn has_changed(&self) -> bool {
         self.changed
     }
+
+    /// Lines of the current run.
+    pub fn current(&self) -> &[String] {
+        &self.current
+    }
+
+    /// Returns the problem found when comparing the current run, if
+    /// it was not reported before.
+    pub fn take_warning(&mut self) -> Option<String> {
+        self.warning.take()
+    }
 }
 
 impl Iterator for &mut Differ {
     type Item = String;
     fn next(&mut self) -> Option<Self::Item> {
-        assert!(self.changed, "can only iterate over a changed Differ");
-        if self.iline == self.lines.len() {
-            None
-        } else {
-            let i = self.iline;
-            self.iline += 1;
-            Some(self.lines[i].clone())
+        assert!(
+            self.changed || self.shown,
+            "can only iterate over a changed or shown Differ"
+        );
+        let lines = match self.mode {
+            Mode::Lines => &self.current,
+            _ if !self.finished => return None,
+            Mode::Sorted => &self.current,
+            Mode::Unordered | Mode::Json | Mode::Table => &self.diff,
+        };
+        let line = lines.get(self.iline)?.clone();
+        self.iline += 1;
+        Some(line)
+    }
+}
+
+/// Returns the lines of `old` that are not in `new` and the lines of
+/// `new` that are not in `old`, considering repetitions.
+fn multiset_diff<'a>(old: &'a [String], new: &'a [String]) -> (Vec<&'a str>, Vec<&'a str>) {
+    fn unmatched<'a>(lines: &'a [String], other: &'a [String]) -> Vec<&'a str> {
+        let mut counts = HashMap::<&str, usize>::new();
+        for line in other {
+            *counts.entry(line).or_default() += 1;
         }
+        lines
+            .iter()
+            .filter(|line| match counts.get_mut(line.as_str()) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                }
+                _ => true,
+            })
+            .map(String::as_str)
+            .collect()
     }
+    (unmatched(old, new), unmatched(new, old))
 }
 
 #[cfg(test)]
@@ -82,4 +327,203 @@ pub mod test {
         assert!(d.has_changed());
         assert_eq!(d.collect::<Vec<_>>(), vec!["1", "2", "3"]);
     }
+
+    #[test]
+    fn test_finish() {
+        let mut d = Differ::default();
+        d.push("1".to_owned());
+        d.push("2".to_owned());
+        d.finish();
+        assert_eq!(d.collect::<Vec<_>>(), vec!["1", "2"]);
+        d.reset();
+        d.push("1".to_owned());
+        assert!(!d.has_changed());
+        d.finish();
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["1"]);
+        d.reset();
+        d.push("1".to_owned());
+        d.finish();
+        assert!(!d.has_changed());
+    }
+
+    #[test]
+    fn test_show() {
+        let mut d = Differ::default();
+        d.push("1".to_owned());
+        d.push("2".to_owned());
+        d.finish();
+        d.reset();
+        d.push("1".to_owned());
+        d.push("2".to_owned());
+        d.show();
+        assert!(!d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["1", "2"]);
+        d.push("3".to_owned());
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["3"]);
+    }
+
+    #[test]
+    fn test_pin() {
+        let mut d = Differ::default();
+        d.push("1".to_owned());
+        d.finish();
+        d.pin();
+        d.reset();
+        d.push("2".to_owned());
+        d.finish();
+        assert!(d.has_changed());
+        d.reset();
+        // Still compared against the pinned run:
+        d.push("1".to_owned());
+        d.finish();
+        assert!(!d.has_changed());
+    }
+
+    #[test]
+    fn test_pinned() {
+        let mut d = Differ::pinned(Mode::Lines, vec!["a".to_owned(), "b".to_owned()]);
+        d.push("a".to_owned());
+        d.finish();
+        assert!(d.has_changed());
+        d.reset();
+        d.push("a".to_owned());
+        d.push("b".to_owned());
+        d.finish();
+        assert!(!d.has_changed());
+    }
+
+    #[test]
+    fn test_resumed() {
+        let lines = vec!["a".to_owned(), "b".to_owned()];
+        let mut d = Differ::resumed(Mode::Lines, lines.clone())
+            .with_tolerance(Some(Tolerance::Absolute(1.0)));
+        d.reset();
+        d.push("a".to_owned());
+        d.push("b".to_owned());
+        d.finish();
+        assert!(!d.has_changed());
+        assert_eq!(d.next_reference(), &lines);
+        d.reset();
+        d.push("c".to_owned());
+        d.finish();
+        assert!(d.has_changed());
+        assert_eq!(d.next_reference(), &["c".to_owned()]);
+    }
+
+    fn push_all(d: &mut Differ, lines: &[&str]) {
+        d.reset();
+        for line in lines {
+            d.push(line.to_string());
+        }
+        d.finish();
+    }
+
+    #[test]
+    fn test_sorted() {
+        let mut d = Differ::new(Mode::Sorted);
+        d.reset();
+        d.push("b".to_owned());
+        d.push("a".to_owned());
+        assert!(!d.has_changed());
+        // Nothing is yielded before the run finishes:
+        d.show();
+        assert_eq!(d.collect::<Vec<_>>(), Vec::<String>::new());
+        d.finish();
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["a", "b"]);
+        push_all(&mut d, &["a", "b"]);
+        assert!(!d.has_changed());
+        push_all(&mut d, &["b", "c", "a"]);
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn test_unordered() {
+        let mut d = Differ::new(Mode::Unordered);
+        push_all(&mut d, &["a", "b", "b"]);
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["+ a", "+ b", "+ b"]);
+        push_all(&mut d, &["b", "a", "b"]);
+        assert!(!d.has_changed());
+        d.show();
+        assert_eq!(d.collect::<Vec<_>>(), Vec::<String>::new());
+        push_all(&mut d, &["c", "b", "a"]);
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["- b", "+ c"]);
+    }
+
+    #[test]
+    fn test_tolerance() {
+        let mut d = Differ::new(Mode::Lines).with_tolerance(Some(Tolerance::Relative(0.05)));
+        push_all(&mut d, &["load 100", "up"]);
+        push_all(&mut d, &["load 103", "up"]);
+        assert!(!d.has_changed());
+        // Compared against the last changed run, so that the drift is
+        // eventually detected:
+        push_all(&mut d, &["load 106", "up"]);
+        assert!(d.has_changed());
+        // The output is shown as is:
+        assert_eq!(d.collect::<Vec<_>>(), vec!["load 106", "up"]);
+        push_all(&mut d, &["load 106"]);
+        assert!(d.has_changed());
+    }
+
+    #[test]
+    fn test_json() -> color_eyre::Result<()> {
+        let selector = ".items[].phase".parse::<Selector>()?;
+        let mut d = Differ::new(Mode::Json).with_selector(Some(selector));
+        push_all(&mut d, &["{\"items\": [{\"phase\": \"Running\"}]}"]);
+        assert!(d.has_changed());
+        assert_eq!(
+            d.collect::<Vec<_>>(),
+            vec!["added .items[0].phase: \"Running\""]
+        );
+        // Formatting and other fields don't matter:
+        push_all(
+            &mut d,
+            &["{\"items\": [", "{\"x\": 1, \"phase\": \"Running\"}]}"],
+        );
+        assert!(!d.has_changed());
+        push_all(&mut d, &["{\"items\": [{\"phase\": \"Failed\"}]}"]);
+        assert!(d.has_changed());
+        assert_eq!(
+            d.collect::<Vec<_>>(),
+            vec!["changed .items[0].phase: \"Running\" → \"Failed\""]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_table() {
+        let mut d = Differ::new(Mode::Table).with_key_column(Some("NAME".to_owned()));
+        push_all(&mut d, &["ID  NAME  STATUS", "1   web   up"]);
+        push_all(&mut d, &["ID  NAME  STATUS", "1   web   up"]);
+        assert!(!d.has_changed());
+        push_all(&mut d, &["ID  NAME  STATUS", "2   web   up"]);
+        assert!(d.has_changed());
+        assert_eq!(d.collect::<Vec<_>>(), vec!["changed web: ID 1 → 2"]);
+        assert_eq!(d.take_warning(), None);
+    }
+
+    #[test]
+    fn test_table_missing_key() {
+        let mut d = Differ::new(Mode::Table).with_key_column(Some("NAMES".to_owned()));
+        push_all(&mut d, &["ID  NAME  STATUS", "1   web   up"]);
+        assert_eq!(
+            d.take_warning().as_deref(),
+            Some("key column NAMES is not in the header, using ID")
+        );
+        push_all(&mut d, &["ID  NAME  STATUS", "1   web   up"]);
+        assert_eq!(d.take_warning(), None);
+    }
+
+    #[test]
+    fn test_pinned_sorted() {
+        let mut d = Differ::pinned(Mode::Sorted, vec!["b".to_owned(), "a".to_owned()]);
+        push_all(&mut d, &["a", "b"]);
+        assert!(!d.has_changed());
+    }
 }
@@ -7,9 +7,15 @@
 //! events.
 
 use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
 use pin_project::pin_project;
+use regex::Regex;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::PathBuf;
 use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::task::{Context, Poll, ready};
 use tokio_stream::Stream;
 use tokio_stream::wrappers::IntervalStream;
 use tracing::instrument;
@@ -18,6 +24,8 @@ use crate::process_wrapper;
 use crate::process_wrapper::Cmd;
 use crate::process_wrapper::ExitSts;
 use crate::process_wrapper::ProcessStream;
+use crate::record::Recorder;
+use crate::record::Replay;
 use crate::sys::SysApi;
 use crate::time_wrapper::Duration;
 use crate::time_wrapper::Instant;
@@ -26,15 +34,24 @@ use crate::user_wrapper::UserStream;
 
 // EData, EItem //////////////////////////////////////////////////////
 
+/// The data of the items yielded by the [`Engine`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EData {
+    /// A run of the command has started.
     StartRun,
+    /// We are sleeping until the provided deadline.
     StartSleep(Instant),
+    /// A line printed by the command in its stdout.
     LineOut(String),
+    /// A line printed by the command in its stderr.
     LineErr(String),
+    /// A message for the user, like the reason for exiting.
     Msg(String),
+    /// The command has exited with the provided status.
     Done(ExitSts),
+    /// Error running the command.
     Err(std::io::ErrorKind),
+    /// Periodic item, for animations.
     Tick,
 }
 
@@ -67,45 +84,163 @@ impl From<std::io::Error> for EData {
     }
 }
 
+/// A timestamped item yielded by the [`Engine`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct EItem {
+    /// When the item was generated.
     pub time: Instant,
+    /// Id of the run that generated the item, if it came from one.
+    pub run: Option<u32>,
+    /// What happened.
     pub data: EData,
 }
 
 impl EItem {
+    /// Creates an item that didn't come from a run.
     pub fn new<D>(time: Instant, data: D) -> EItem
     where
         D: Into<EData>,
     {
         Self {
             time,
+            run: None,
             data: data.into(),
         }
     }
 
+    /// Creates an item with a message for the user.
     pub fn msg(time: Instant, msg: String) -> EItem {
         Self {
             time,
+            run: None,
             data: EData::Msg(msg),
         }
     }
+
+    /// Tags the item with the id of the run that generated it.
+    pub fn with_run(mut self, run: u32) -> EItem {
+        self.run = Some(run);
+        self
+    }
 }
 
 // Engine ////////////////////////////////////////////////////////////
 
+/// Conditions that make the engine exit after a run.
+#[derive(Debug, Default, Clone)]
+pub struct ExitConditions {
+    /// Exit when the command exits with success.
+    pub success: bool,
+    /// Exit when the command exits with a failure.
+    pub failure: bool,
+    /// Exit when a line of the output matches.
+    pub matching: Option<Regex>,
+    /// Exit when no line of the output matches.
+    pub not_matching: Option<Regex>,
+}
+
+/// The lines of a run that the [`ExitConditions`] look at.
+#[derive(Debug)]
+struct Matches {
+    /// First line that matched [`ExitConditions::matching`].
+    matched: Option<String>,
+    /// If no line matched [`ExitConditions::not_matching`].
+    not_matched: bool,
+}
+
+impl Default for Matches {
+    fn default() -> Self {
+        Self {
+            matched: None,
+            not_matched: true,
+        }
+    }
+}
+
+impl Matches {
+    fn check_line(&mut self, exit: &ExitConditions, line: &str) {
+        if self.matched.is_none() && exit.matching.as_ref().is_some_and(|r| r.is_match(line)) {
+            self.matched = Some(line.to_string());
+        }
+        if exit.not_matching.as_ref().is_some_and(|r| r.is_match(line)) {
+            self.not_matched = false;
+        }
+    }
+
+    /// Returns the message we show if the run satisfies an exit
+    /// condition that depends on its output.
+    fn exit_msg(&self, exit: &ExitConditions) -> Option<String> {
+        if exit.matching.is_some()
+            && let Some(line) = &self.matched
+        {
+            Some(format!("line matched: {line}"))
+        } else if let Some(regex) = &exit.not_matching
+            && self.not_matched
+        {
+            Some(format!("no line matched {regex}"))
+        } else {
+            None
+        }
+    }
+}
+
+/// A process in flight.
+#[derive(Debug)]
+struct Run {
+    id: u32,
+    /// The process of the command or, after it exits, of the filter.
+    process: ProcessStream,
+    matches: Matches,
+    /// The stdout lines of the command, stored while it runs if they
+    /// have to go through a filter.
+    unfiltered: Option<Vec<String>>,
+    /// Exit status of the command, stored while the filter runs.
+    exitsts: Option<ExitSts>,
+}
+
+impl Run {
+    fn new(id: u32, process: ProcessStream, filtered: bool) -> Self {
+        Self {
+            id,
+            process,
+            matches: Matches::default(),
+            unfiltered: filtered.then(Vec::new),
+            exitsts: None,
+        }
+    }
+}
+
+/// When to start the next overlapping run.
+#[derive(Debug)]
+struct Next {
+    deadline: Instant,
+    /// Wakes us up at the `deadline`.
+    timer: Pin<Box<tokio::time::Sleep>>,
+}
+
+impl Next {
+    fn new(now: Instant, deadline: Instant) -> Self {
+        let timer = Box::pin(tokio::time::sleep((&deadline - &now).into()));
+        Self { deadline, timer }
+    }
+}
+
 #[derive(Debug, Default)]
 enum State {
     /// State where we start the process on the next iteration.
     #[default]
     Start,
-    StartSleeping,
-    /// The process is running and we are yielding lines and ticks.
+    /// State where we start sleeping until the provided deadline.
+    StartSleeping(Instant),
+    /// The processes are running and we are yielding lines and ticks.
     Running {
-        /// Events coming from the running process
-        process: ProcessStream,
+        /// The processes in flight
+        runs: Vec<Run>,
         /// Tick events generated by the [`IntervalStream`] timer
         ticker: IntervalStream,
+        /// When to start the next overlapping run, if we are allowed
+        /// to run more than one process at a time.
+        next: Option<Next>,
     },
     /// Sleeping between two process executions, yielding ticks.
     Sleeping {
@@ -125,6 +260,8 @@ impl State {
     }
 }
 
+/// Stream of [`EItem`]s that runs the command periodically; created
+/// with an [`EngineBuilder`].
 #[pin_project(project = EngineProjection)]
 #[derive(Default, Debug)]
 pub struct Engine<SI: SysApi> {
@@ -132,50 +269,245 @@ pub struct Engine<SI: SysApi> {
     cmd: Cmd,
     refresh: Duration,
     sleep: Duration,
-    exit_on_success: bool,
-    exit_on_failure: bool,
+    max_concurrent: usize,
+    exit: ExitConditions,
+    /// Command that the stdout of each run goes through.
+    filter: Option<Cmd>,
     state: State,
     user: Option<UserStream>,
     exit_by_user: bool,
+    /// An exit condition was met, don't start any other run.
+    exiting: bool,
+    /// Id of the last run started.
+    last_run: u32,
+    /// Items to yield before anything else in the next calls.
+    pending: VecDeque<EItem>,
+    /// Where we record the items we yield.
+    recorder: Option<Recorder>,
+    /// Recorded items that we yield instead of running the command.
+    replay: Option<Replay>,
+    /// The replayed runs in flight.
+    replayed: BTreeMap<u32, Matches>,
 }
 
 impl<SI: SysApi> Engine<SI> {
-    pub fn new(
-        mut sys: SI,
-        cmd: Cmd,
-        refresh: Duration,
-        sleep: Duration,
-        exit_on_success: bool,
-        exit_on_failure: bool,
-    ) -> Result<Self> {
-        let user_stream = sys.user_stream();
-        Ok(Self {
-            sys,
+    /// Gives access to the system functions, so that consumers of the
+    /// stream can also use them.
+    pub fn sys(&self) -> &SI {
+        &self.sys
+    }
+
+    /// Gives mutable access to the system functions.
+    pub fn sys_mut(&mut self) -> &mut SI {
+        &mut self.sys
+    }
+}
+
+// EngineBuilder /////////////////////////////////////////////////////
+
+/// Builder for [`Engine`], with the same defaults as the command line.
+#[derive(Debug, Clone)]
+pub struct EngineBuilder {
+    cmd: Cmd,
+    refresh: Duration,
+    period: Duration,
+    max_concurrent: usize,
+    exit: ExitConditions,
+    filter: Option<Cmd>,
+    record: Option<PathBuf>,
+    user_input: bool,
+}
+
+impl EngineBuilder {
+    /// Creates a builder for an engine that runs `cmd` every second.
+    pub fn new(cmd: Cmd) -> Self {
+        Self {
             cmd,
-            refresh,
-            sleep,
-            exit_on_success,
-            exit_on_failure,
+            refresh: Duration::milliseconds(250),
+            period: Duration::seconds(1),
+            max_concurrent: 1,
+            exit: ExitConditions::default(),
+            filter: None,
+            record: None,
+            user_input: false,
+        }
+    }
+
+    /// Sets the time between the end of a run and the start of the
+    /// next one, or between the starts of overlapping runs.
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// Sets the interval of the [`EData::Tick`] items.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// Sets how many runs can be in flight at the same time; with more
+    /// than one, a run starts every period.
+    pub fn with_max_concurrent(mut self, max_concurrent: usize) -> Self {
+        self.max_concurrent = max_concurrent;
+        self
+    }
+
+    /// Sets the conditions that make the engine exit after a run.
+    pub fn with_exit(mut self, exit: ExitConditions) -> Self {
+        self.exit = exit;
+        self
+    }
+
+    /// Sets the command that filters the stdout of each run before we
+    /// yield it; the lines are yielded when the filter exits, and its
+    /// exit status is ignored.
+    pub fn with_filter(mut self, filter: Option<Cmd>) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Records the items we yield in the file at `path`.
+    pub fn with_record(mut self, path: Option<PathBuf>) -> Self {
+        self.record = path;
+        self
+    }
+
+    /// Reads the keys the user presses, which puts the terminal in raw
+    /// mode while the engine exists.
+    pub fn with_user_input(mut self, user_input: bool) -> Self {
+        self.user_input = user_input;
+        self
+    }
+
+    /// Creates the engine, which uses `sys` to run the command; library
+    /// users pass a [`SysReal`](crate::sys::SysReal).
+    pub fn build<SI: SysApi>(self, mut sys: SI) -> Result<Engine<SI>> {
+        let recorder = match self.record {
+            Some(path) => Some(
+                Recorder::new(&mut sys, path.clone(), &self.cmd, self.max_concurrent)
+                    .wrap_err_with(|| format!("error creating recording {}", path.display()))?,
+            ),
+            None => None,
+        };
+        let user = if self.user_input {
+            sys.user_stream()
+        } else {
+            None
+        };
+        let replay = (sys.replay_stream()).map(|replay| replay.with_refresh(self.refresh));
+        Ok(Engine {
+            sys,
+            cmd: self.cmd,
+            refresh: self.refresh,
+            sleep: self.period,
+            max_concurrent: self.max_concurrent,
+            exit: self.exit,
+            filter: self.filter,
             state: State::Start,
-            user: user_stream,
+            user,
             exit_by_user: false,
+            exiting: false,
+            last_run: 0,
+            pending: VecDeque::default(),
+            recorder,
+            replay,
+            replayed: BTreeMap::default(),
         })
     }
 }
 
 impl<SI: SysApi> EngineProjection<'_, SI> {
-    fn sleep(&mut self, now: Instant) -> EItem {
-        let deadline = &now + self.sleep;
+    /// Checks the exit conditions when a run exits, queueing the
+    /// message of the condition that depends on the output, if any.
+    fn check_exit(&mut self, now: Instant, matches: &Matches, exitsts: &ExitSts) {
+        let success = exitsts.success();
+        if *self.exit_by_user || success && self.exit.success || !success && self.exit.failure {
+            *self.exiting = true;
+        }
+        if let Some(msg) = matches.exit_msg(self.exit) {
+            *self.exiting = true;
+            self.pending.push_back(EItem::msg(now, msg));
+        }
+    }
+
+    /// Stops starting runs after one of them failed with an I/O error,
+    /// letting the others in the `state` finish.
+    fn fail_run(&mut self, state: State) {
+        *self.exiting = true;
+        *self.state = match state {
+            State::Running { ref runs, .. } if !runs.is_empty() => state,
+            _ => State::Done,
+        };
+    }
+
+    /// Yields the next recorded item, checking the exit conditions as
+    /// if the replayed runs were running now.
+    fn poll_replay(&mut self, cx: &mut Context<'_>) -> Poll<Option<EItem>> {
+        let Some(replay) = self.replay.as_mut() else {
+            return Poll::Ready(None);
+        };
+        loop {
+            if *self.exiting && self.replayed.is_empty() {
+                return Poll::Ready(None);
+            }
+            let Some(item) = ready!(Pin::new(&mut *replay).poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            let Some(run) = item.run else {
+                return Poll::Ready(Some(item));
+            };
+            match &item.data {
+                EData::StartRun if *self.exiting => {
+                    // We would not have started this run.
+                    continue;
+                }
+                EData::StartRun => {
+                    self.replayed.insert(run, Matches::default());
+                }
+                _ if !self.replayed.contains_key(&run) => continue,
+                EData::LineOut(line) | EData::LineErr(line) => {
+                    if let Some(matches) = self.replayed.get_mut(&run) {
+                        matches.check_line(self.exit, line);
+                    }
+                }
+                EData::Done(exitsts) => {
+                    if let Some(matches) = self.replayed.remove(&run) {
+                        self.check_exit(item.time, &matches, exitsts);
+                    }
+                }
+                EData::Err(_) => {
+                    self.replayed.remove(&run);
+                }
+                _ => {}
+            }
+            return Poll::Ready(Some(item));
+        }
+    }
+
+    fn sleep(&mut self, now: Instant, deadline: Instant) -> EItem {
         let ticker = IntervalStream::new((*self.refresh).into());
         *self.state = State::Sleeping { deadline, ticker };
         EItem::new(now, EData::StartSleep(deadline))
     }
 
-    fn run(&mut self) -> std::result::Result<(), std::io::Error> {
+    fn spawn(&mut self) -> std::result::Result<Run, std::io::Error> {
         let process = self.sys.run_command(self.cmd.clone())?;
+        *self.last_run += 1;
+        Ok(Run::new(*self.last_run, process, self.filter.is_some()))
+    }
+
+    fn run(&mut self, now: Instant) -> std::result::Result<u32, std::io::Error> {
+        let run = self.spawn()?;
+        let id = run.id;
         let ticker = IntervalStream::new((*self.refresh).into());
-        *self.state = State::Running { process, ticker };
-        Ok(())
+        let next = (*self.max_concurrent > 1).then(|| Next::new(now, &now + self.sleep));
+        *self.state = State::Running {
+            runs: vec![run],
+            ticker,
+            next,
+        };
+        Ok(id)
     }
 }
 
@@ -184,7 +516,26 @@ impl<SI: SysApi> Stream for Engine<SI> {
 
     #[instrument(level = "debug", ret, skip(cx))]
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = self.as_mut().poll_item(cx);
+        let this = self.project();
+        if let (Poll::Ready(Some(item)), Some(recorder)) = (&polled, &this.recorder)
+            && let Err(e) = recorder.record(this.sys, item)
+        {
+            // Stop recording, as the recording is now incomplete:
+            *this.recorder = None;
+            let msg = format!("error writing recording: {e}");
+            this.pending.push_back(EItem::msg(item.time, msg));
+        }
+        polled
+    }
+}
+
+impl<SI: SysApi> Engine<SI> {
+    fn poll_item(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<EItem>> {
         let mut this = self.as_mut().project();
+        if let Some(item) = this.pending.pop_front() {
+            return Poll::Ready(Some(item));
+        }
         let now = this.sys.now();
         if let Some(user) = this.user {
             match Pin::new(user).poll_next(cx) {
@@ -200,10 +551,12 @@ impl<SI: SysApi> Stream for Engine<SI> {
                 }
                 Poll::Ready(Some(UserEvent::Kill)) => {
                     *this.exit_by_user = true;
-                    if let State::Running { process, ticker: _ } = this.state
-                        && let Some(child) = process.child_mut()
-                    {
-                        let _ = child.start_kill();
+                    if let State::Running { runs, .. } = this.state {
+                        for run in runs.iter_mut() {
+                            if let Some(child) = run.process.child_mut() {
+                                let _ = child.start_kill();
+                            }
+                        }
                         return Poll::Ready(Some(EItem::msg(now, "user exit, forced".to_string())));
                     } else {
                         return Poll::Ready(Some(EItem::msg(now, "user exit".to_string())));
@@ -215,20 +568,27 @@ impl<SI: SysApi> Stream for Engine<SI> {
                 Poll::Pending => {}
             };
         }
+        if this.replay.is_some() {
+            return if *this.exit_by_user {
+                Poll::Ready(None)
+            } else {
+                this.poll_replay(cx)
+            };
+        }
         let mut state = std::mem::take(&mut *this.state);
-        return match state {
+        match state {
             State::Start => {
-                let ret = this.run();
+                let ret = this.run(now);
                 match ret {
-                    Ok(_) => Poll::Ready(Some(EItem::new(now, EData::StartRun))),
+                    Ok(run) => Poll::Ready(Some(EItem::new(now, EData::StartRun).with_run(run))),
                     Err(e) => {
                         *this.state = State::Done;
                         Poll::Ready(Some(EItem::new(now, e)))
                     }
                 }
             }
-            State::StartSleeping => {
-                let item = this.sleep(now);
+            State::StartSleeping(deadline) => {
+                let item = this.sleep(now, deadline);
                 Poll::Ready(Some(item))
             }
             State::Sleeping {
@@ -253,55 +613,155 @@ impl<SI: SysApi> Stream for Engine<SI> {
                 }
             }
             State::Running {
-                ref mut process,
+                ref mut runs,
                 ref mut ticker,
-            } => match Pin::new(process).poll_next(cx) {
-                Poll::Ready(Some(item)) => match item {
-                    process_wrapper::Item::Stdout(_) => {
-                        *this.state = state;
-                        Poll::Ready(Some(EItem::new(now, item)))
+                ref mut next,
+            } => {
+                let polled = loop {
+                    let polled = runs.iter_mut().enumerate().find_map(|(i, run)| {
+                        match Pin::new(&mut run.process).poll_next(cx) {
+                            Poll::Ready(item) => Some((i, item)),
+                            Poll::Pending => None,
+                        }
+                    });
+                    // The stdout lines that go through the filter are
+                    // stored until the command exits, when we start the
+                    // filter with them:
+                    match polled {
+                        Some((i, Some(process_wrapper::Item::Stdout(line))))
+                            if let Some(unfiltered) = &mut runs[i].unfiltered =>
+                        {
+                            unfiltered.push(line);
+                        }
+                        Some((i, Some(process_wrapper::Item::Done(Ok(exitsts)))))
+                            if let Some(unfiltered) = runs[i].unfiltered.take() =>
+                        {
+                            let filter = this.filter.clone().unwrap_or_default();
+                            match this.sys.run_filter(filter, unfiltered) {
+                                Ok(process) => {
+                                    runs[i].process = process;
+                                    runs[i].exitsts = Some(exitsts);
+                                }
+                                Err(e) => {
+                                    break Some((
+                                        i,
+                                        Some(process_wrapper::Item::Done(Err(e.kind()))),
+                                    ));
+                                }
+                            }
+                        }
+                        polled => break polled,
                     }
-                    process_wrapper::Item::Stderr(_) => {
-                        *this.state = state;
-                        Poll::Ready(Some(EItem::new(now, item)))
+                };
+                match polled {
+                    Some((i, Some(item))) => {
+                        let run = runs[i].id;
+                        match item {
+                            process_wrapper::Item::Stdout(ref line) => {
+                                runs[i].matches.check_line(this.exit, line);
+                                *this.state = state;
+                                Poll::Ready(Some(EItem::new(now, item).with_run(run)))
+                            }
+                            process_wrapper::Item::Stderr(ref line) => {
+                                runs[i].matches.check_line(this.exit, line);
+                                *this.state = state;
+                                Poll::Ready(Some(EItem::new(now, item).with_run(run)))
+                            }
+                            process_wrapper::Item::Done(Ok(exitsts)) => {
+                                // The exit status of the command, not of the filter,
+                                // which we report if it failed:
+                                let (exitsts, failed_filter) = match runs[i].exitsts.take() {
+                                    Some(cmd_exitsts) => {
+                                        (cmd_exitsts, Some(exitsts).filter(|sts| !sts.success()))
+                                    }
+                                    None => (exitsts, None),
+                                };
+                                let done = runs.remove(i);
+                                this.check_exit(now, &done.matches, &exitsts);
+                                if !runs.is_empty() {
+                                    *this.state = state;
+                                } else if *this.exiting {
+                                    *this.state = State::Done;
+                                } else {
+                                    let deadline = (next.as_ref())
+                                        .map_or_else(|| &now + this.sleep, |next| next.deadline);
+                                    *this.state = State::StartSleeping(deadline);
+                                }
+                                let item = EItem::new(now, EData::Done(exitsts)).with_run(run);
+                                if let Some(filter_exitsts) = failed_filter {
+                                    this.pending.push_front(item);
+                                    let msg = format!("filter exited with {filter_exitsts}");
+                                    return Poll::Ready(Some(EItem::msg(now, msg)));
+                                }
+                                Poll::Ready(Some(item))
+                            }
+                            process_wrapper::Item::Done(Err(e)) => {
+                                runs.remove(i);
+                                this.fail_run(state);
+                                Poll::Ready(Some(EItem::new(now, e).with_run(run)))
+                            }
+                        }
                     }
-                    process_wrapper::Item::Done(Ok(ref exitsts)) => {
-                        let success = exitsts.success();
-                        if *this.exit_by_user
-                            || success && *this.exit_on_success
-                            || !success && *this.exit_on_failure
-                        {
+                    Some((i, None)) => {
+                        // The process ended without an exit status:
+                        runs.remove(i);
+                        if runs.is_empty() {
                             *this.state = State::Done;
+                            Poll::Ready(None)
                         } else {
-                            *this.state = State::StartSleeping;
+                            *this.state = state;
+                            self.poll_item(cx)
                         }
-                        Poll::Ready(Some(EItem::new(now, item)))
-                    }
-                    process_wrapper::Item::Done(Err(e)) => {
-                        *this.state = State::Done;
-                        Poll::Ready(Some(EItem::new(now, e)))
                     }
-                },
-                Poll::Ready(None) => {
-                    *this.state = State::Done;
-                    Poll::Ready(None)
-                }
-                Poll::Pending => {
-                    // Process doesn't have an item, it must be the ticker
-                    if let Poll::Ready(Some(_)) = Pin::new(ticker).poll_next(cx) {
-                        *this.state = state;
-                        Poll::Ready(Some(EItem::new(now, EData::Tick)))
-                    } else {
-                        *this.state = state;
-                        Poll::Pending
+                    None => {
+                        // No process has an item: start an overlapping run
+                        // if its timer is done and we have room, or tick
+                        let room = runs.len() < *this.max_concurrent
+                            && !*this.exiting
+                            && !*this.exit_by_user;
+                        let due = match next {
+                            Some(next) if room => {
+                                (next.timer.as_mut().poll(cx).is_ready()).then_some(next.deadline)
+                            }
+                            _ => None,
+                        };
+                        if let Some(deadline) = due {
+                            // Keep the schedule unless we are already behind it:
+                            let scheduled = &deadline + this.sleep;
+                            let deadline = if scheduled > now {
+                                scheduled
+                            } else {
+                                &now + this.sleep
+                            };
+                            *next = Some(Next::new(now, deadline));
+                            let item = match this.spawn() {
+                                Ok(run) => {
+                                    let id = run.id;
+                                    runs.push(run);
+                                    EItem::new(now, EData::StartRun).with_run(id)
+                                }
+                                Err(e) => {
+                                    *this.exiting = true;
+                                    EItem::new(now, e)
+                                }
+                            };
+                            *this.state = state;
+                            Poll::Ready(Some(item))
+                        } else if let Poll::Ready(Some(_)) = Pin::new(ticker).poll_next(cx) {
+                            *this.state = state;
+                            Poll::Ready(Some(EItem::new(now, EData::Tick)))
+                        } else {
+                            *this.state = state;
+                            Poll::Pending
+                        }
                     }
                 }
-            },
+            }
             State::Done => {
                 *this.state = state;
                 Poll::Ready(None)
             }
-        };
+        }
     }
 }
 
@@ -310,33 +770,39 @@ impl<SI: SysApi> Stream for Engine<SI> {
 #[cfg(test)]
 mod tests {
     use color_eyre::Result;
+    use std::collections::VecDeque;
     use std::io;
     use tokio_stream::StreamExt;
 
     use crate::process_wrapper::Item;
+    use crate::sys::Script;
+    use crate::sys::SysReplay;
     use crate::sys::SysVirtual;
     use crate::time_wrapper::Instant;
 
     use super::*;
 
+    /// Returns the builder of an engine that never ticks nor sleeps.
+    fn virtual_builder(exit_on_success: bool, exit_on_failure: bool) -> EngineBuilder {
+        let exit = ExitConditions {
+            success: exit_on_success,
+            failure: exit_on_failure,
+            ..Default::default()
+        };
+        EngineBuilder::new(Cmd::default())
+            .with_refresh(Duration::INFINITE)
+            .with_period(Duration::INFINITE)
+            .with_exit(exit)
+    }
+
     impl Engine<SysVirtual> {
+        /// Creates an engine that yields the items of `sys`.
         pub fn new_virtual(
-            mut sys: SysVirtual,
+            sys: SysVirtual,
             exit_on_success: bool,
             exit_on_failure: bool,
         ) -> Result<Self> {
-            let user_stream = sys.user_stream();
-            Ok(Self {
-                sys,
-                cmd: Cmd::default(),
-                refresh: Duration::INFINITE,
-                sleep: Duration::INFINITE,
-                exit_on_success,
-                exit_on_failure,
-                state: State::Start,
-                user: user_stream,
-                exit_by_user: false,
-            })
+            virtual_builder(exit_on_success, exit_on_failure).build(sys)
         }
     }
 
@@ -359,26 +825,32 @@ mod tests {
             vec![
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::StartRun
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::LineOut("line1".into())
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::LineOut("line2".into())
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::LineErr("err1".into())
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::LineOut("line3".into())
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::Done(ExitSts::default())
                 },
             ]
@@ -386,6 +858,67 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_filter() -> Result<()> {
+        let list = vec![
+            Item::Stdout("line1".into()),
+            Item::Stderr("err1".into()),
+            Item::Stdout("line2".into()),
+            Item::Done(Ok(ExitSts::Code(1))),
+        ];
+        let mut sys = SysVirtual::default();
+        sys.set_items(list);
+        let streamer = virtual_builder(false, true)
+            .with_filter(Some(Cmd::default()))
+            .build(sys)?;
+        let streamed = streamer.map(|item| item.data).collect::<Vec<_>>().await;
+        // The stdout lines go through the filter, which SysVirtual
+        // implements as the identity, after the command exits:
+        assert_eq!(
+            streamed,
+            vec![
+                EData::StartRun,
+                EData::LineErr("err1".into()),
+                EData::LineOut("line1".into()),
+                EData::LineOut("line2".into()),
+                EData::Done(ExitSts::Code(1)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_filter_failure() -> Result<()> {
+        let filter = vec![
+            Item::Stderr("parse error".into()),
+            Item::Done(Ok(ExitSts::Code(5))),
+        ];
+        let sys = SysVirtual::default();
+        let mut streamer = Engine::new_virtual(sys, true, false)?;
+        let mut run = Run::new(1, ProcessStream::from(VecDeque::from(filter)), false);
+        // The command succeeded, and now the filter runs:
+        run.exitsts = Some(ExitSts::Success);
+        streamer.last_run = 1;
+        streamer.state = State::Running {
+            runs: vec![run],
+            ticker: IntervalStream::new(Duration::INFINITE.into()),
+            next: None,
+        };
+        let streamed = streamer
+            .map(|item| (item.run, item.data))
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(
+            streamed,
+            vec![
+                (Some(1), EData::LineErr("parse error".into())),
+                (None, EData::Msg("filter exited with code 5".into())),
+                (Some(1), EData::Done(ExitSts::Success)),
+            ]
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_done_err() -> Result<()> {
         let list = vec![Item::Done(Err(io::ErrorKind::UnexpectedEof))];
@@ -399,10 +932,12 @@ mod tests {
             vec![
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::StartRun,
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::Err(io::ErrorKind::UnexpectedEof)
                 }
             ]
@@ -410,6 +945,71 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_concurrent_done_err() -> Result<()> {
+        let run1 = vec![Item::Done(Err(io::ErrorKind::UnexpectedEof))];
+        let run2 = vec![Item::Stdout("b".into()), Item::Done(Ok(ExitSts::Code(1)))];
+        let sys = SysVirtual::default();
+        let mut streamer = Engine::new_virtual(sys, false, false)?;
+        streamer.max_concurrent = 2;
+        streamer.last_run = 2;
+        streamer.state = State::Running {
+            runs: vec![
+                Run::new(1, ProcessStream::from(VecDeque::from(run1)), false),
+                Run::new(2, ProcessStream::from(VecDeque::from(run2)), false),
+            ],
+            ticker: IntervalStream::new(Duration::INFINITE.into()),
+            next: None,
+        };
+        let streamed = streamer
+            .map(|item| (item.run, item.data))
+            .collect::<Vec<_>>()
+            .await;
+        // Run 2 is not affected by the error in run 1, but we don't
+        // start other runs after it:
+        assert_eq!(
+            streamed,
+            vec![
+                (Some(1), EData::Err(io::ErrorKind::UnexpectedEof)),
+                (Some(2), EData::LineOut("b".into())),
+                (Some(2), EData::Done(ExitSts::Code(1))),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_no_done() -> Result<()> {
+        let run1 = vec![Item::Stdout("a".into())];
+        let run2 = vec![Item::Stdout("b".into()), Item::Done(Ok(ExitSts::Code(1)))];
+        let sys = SysVirtual::default();
+        let mut streamer = Engine::new_virtual(sys, false, true)?;
+        streamer.max_concurrent = 2;
+        streamer.last_run = 2;
+        streamer.state = State::Running {
+            runs: vec![
+                Run::new(1, ProcessStream::from(VecDeque::from(run1)), false),
+                Run::new(2, ProcessStream::from(VecDeque::from(run2)), false),
+            ],
+            ticker: IntervalStream::new(Duration::INFINITE.into()),
+            next: None,
+        };
+        let streamed = streamer
+            .map(|item| (item.run, item.data))
+            .collect::<Vec<_>>()
+            .await;
+        // Run 1 ends without an exit status, which doesn't affect run 2:
+        assert_eq!(
+            streamed,
+            vec![
+                (Some(1), EData::LineOut("a".into())),
+                (Some(2), EData::LineOut("b".into())),
+                (Some(2), EData::Done(ExitSts::Code(1))),
+            ]
+        );
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_exit_on_failure_with_failure() -> Result<()> {
         let list = vec![Item::Done(Ok(ExitSts::Code(1)))];
@@ -423,10 +1023,12 @@ mod tests {
             vec![
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::StartRun,
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::Done(ExitSts::Code(1)),
                 }
             ]
@@ -447,14 +1049,171 @@ mod tests {
             vec![
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::StartRun,
                 },
                 EItem {
                     time: now.incr(),
+                    run: Some(1),
                     data: EData::Done(ExitSts::Success),
                 }
             ]
         );
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_concurrent_runs() -> Result<()> {
+        let run1 = vec![Item::Stdout("a".into()), Item::Done(Ok(ExitSts::Code(1)))];
+        let run2 = vec![Item::Stdout("b".into()), Item::Done(Ok(ExitSts::Success))];
+        let sys = SysVirtual::default();
+        let mut streamer = Engine::new_virtual(sys, true, false)?;
+        streamer.max_concurrent = 2;
+        streamer.last_run = 2;
+        streamer.state = State::Running {
+            runs: vec![
+                Run::new(1, ProcessStream::from(VecDeque::from(run1)), false),
+                Run::new(2, ProcessStream::from(VecDeque::from(run2)), false),
+            ],
+            ticker: IntervalStream::new(Duration::INFINITE.into()),
+            next: None,
+        };
+        let streamed = streamer.collect::<Vec<_>>().await;
+        let mut now = Instant::default();
+        // The failure of run 1 doesn't make us sleep, as run 2 is
+        // still in flight; the success of run 2 makes us exit.
+        assert_eq!(
+            streamed,
+            vec![
+                EItem {
+                    time: now.incr(),
+                    run: Some(1),
+                    data: EData::LineOut("a".into()),
+                },
+                EItem {
+                    time: now.incr(),
+                    run: Some(1),
+                    data: EData::Done(ExitSts::Code(1)),
+                },
+                EItem {
+                    time: now.incr(),
+                    run: Some(2),
+                    data: EData::LineOut("b".into()),
+                },
+                EItem {
+                    time: now.incr(),
+                    run: Some(2),
+                    data: EData::Done(ExitSts::Success),
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_schedule() -> Result<()> {
+        let mut sys = SysVirtual::default().with_virtual_clock();
+        sys.set_runs(vec![
+            Script::default()
+                .wait(Duration::milliseconds(1500))
+                .exit(ExitSts::Success),
+            Script::default()
+                .wait(Duration::milliseconds(200))
+                .exit(ExitSts::Success),
+        ]);
+        // Without ticks, besides the first one, only the timer can
+        // start the second run:
+        let streamer = EngineBuilder::new(Cmd::default())
+            .with_refresh(Duration::INFINITE)
+            .with_period(Duration::seconds(1))
+            .with_max_concurrent(2)
+            .build(sys)?;
+        let streamed = streamer
+            .take(6)
+            .map(|item| (item.time, item.run, item.data))
+            .collect::<Vec<_>>()
+            .await;
+        let at = |millis| &Instant::default() + &Duration::milliseconds(millis);
+        assert_eq!(
+            streamed,
+            vec![
+                (at(0), Some(1), EData::StartRun),
+                (at(0), None, EData::Tick),
+                (at(1000), Some(2), EData::StartRun),
+                (at(1200), Some(2), EData::Done(ExitSts::Success)),
+                (at(1500), Some(1), EData::Done(ExitSts::Success)),
+                (at(1500), None, EData::StartSleep(at(2000))),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_until_match() -> Result<()> {
+        let list = vec![
+            Item::Stdout("starting".into()),
+            Item::Stderr("ready: yes".into()),
+            Item::Done(Ok(ExitSts::Success)),
+        ];
+        let mut sys = SysVirtual::default();
+        sys.set_items(list);
+        let mut streamer = Engine::new_virtual(sys, false, false)?;
+        streamer.exit.matching = Some(Regex::new("^ready")?);
+        let streamed = streamer.collect::<Vec<_>>().await;
+        assert_eq!(streamed.len(), 5);
+        assert_eq!(streamed[3].data, EData::Done(ExitSts::Success));
+        assert_eq!(
+            streamed[4].data,
+            EData::Msg("line matched: ready: yes".into())
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_until_no_match() -> Result<()> {
+        let list = vec![Item::Stdout("ok".into()), Item::Done(Ok(ExitSts::Success))];
+        let mut sys = SysVirtual::default();
+        sys.set_items(list);
+        let mut streamer = Engine::new_virtual(sys, false, false)?;
+        streamer.exit.not_matching = Some(Regex::new("error")?);
+        let streamed = streamer.collect::<Vec<_>>().await;
+        assert_eq!(streamed.len(), 4);
+        assert_eq!(streamed[3].data, EData::Msg("no line matched error".into()));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay_until_match() -> Result<()> {
+        let mut now = Instant::default();
+        let items = VecDeque::from([
+            EItem::new(now.incr(), EData::StartRun).with_run(1),
+            EItem::new(now.incr(), EData::StartRun).with_run(2),
+            EItem::new(now.incr(), EData::LineOut("ready".into())).with_run(1),
+            EItem::new(now.incr(), EData::Done(ExitSts::Success)).with_run(1),
+            EItem::new(now.incr(), EData::StartRun).with_run(3),
+            EItem::new(now.incr(), EData::LineOut("b".into())).with_run(3),
+            EItem::new(now.incr(), EData::Tick),
+            EItem::new(now.incr(), EData::Done(ExitSts::Success)).with_run(2),
+            EItem::new(now.incr(), EData::Done(ExitSts::Success)).with_run(3),
+        ]);
+        let sys = SysReplay::new(items.clone(), 0.0);
+        let exit = ExitConditions {
+            matching: Some(Regex::new("^ready")?),
+            ..Default::default()
+        };
+        let streamer = EngineBuilder::new(Cmd::default())
+            .with_exit(exit)
+            .build(sys)?;
+        let streamed = streamer.collect::<Vec<_>>().await;
+        // Run 3 would not have started, and we end when run 2 exits:
+        let mut expected = Vec::from(items);
+        expected.truncate(8);
+        expected.drain(4..6);
+        expected.insert(
+            4,
+            EItem::msg(expected[3].time, "line matched: ready".into()),
+        );
+        assert_eq!(streamed, expected);
+        Ok(())
+    }
 }
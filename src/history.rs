@@ -0,0 +1,263 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! History of the changed runs, persisted in a directory.
+//!
+//! Each run is stored as `ID.out` with its stdout, `ID.err` with its
+//! stderr, if it has any, and `ID.json` with its metadata. The ID is
+//! the UTC time when the run finished. `index.jsonl` has the metadata
+//! of all runs, one per line, oldest first.
+
+use color_eyre::Result;
+use serde_json::Value;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use crate::process_wrapper::Cmd;
+use crate::process_wrapper::ExitSts;
+use crate::sys::SysApi;
+use crate::time_wrapper::Duration;
+use crate::time_wrapper::Instant;
+
+const INDEX: &str = "index.jsonl";
+
+/// Format of the timestamps in the metadata.
+const TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+/// A run stored in the history.
+#[derive(Debug, Clone)]
+struct Entry {
+    id: String,
+    end: Instant,
+    metadata: Value,
+}
+
+impl Entry {
+    fn parse(line: &str) -> Option<Entry> {
+        let metadata = serde_json::from_str::<Value>(line).ok()?;
+        let id = metadata.get("id")?.as_str()?.to_owned();
+        let end = Instant::from_str(metadata.get("end")?.as_str()?).ok()?;
+        Some(Entry { id, end, metadata })
+    }
+}
+
+/// Limits on the runs we keep.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Retention {
+    /// Maximum number of runs.
+    pub max_runs: Option<usize>,
+    /// Maximum age of the runs, relative to the last one.
+    pub max_age: Option<Duration>,
+}
+
+/// The history directory.
+#[derive(Debug)]
+pub struct History {
+    dir: PathBuf,
+    cmd: Cmd,
+    retention: Retention,
+    /// The runs in the index, oldest first.
+    entries: VecDeque<Entry>,
+}
+
+impl History {
+    /// Opens the history in `dir`, loading its index if it exists.
+    pub fn new<SI: SysApi>(sys: &SI, dir: PathBuf, cmd: Cmd, retention: Retention) -> Result<Self> {
+        let entries = match sys.read_file(&dir.join(INDEX)) {
+            Ok(index) => index.lines().filter_map(Entry::parse).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => VecDeque::default(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            dir,
+            cmd,
+            retention,
+            entries,
+        })
+    }
+
+    fn path(&self, id: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{id}.{extension}"))
+    }
+
+    /// Returns an ID for a run that finished at `end`.
+    fn new_id(&self, end: &Instant) -> String {
+        let base = end.format("%Y%m%dT%H%M%S%.3fZ", true);
+        let taken = |id: &str| self.entries.iter().any(|e| e.id == id);
+        let mut id = base.clone();
+        let mut n = 1;
+        while taken(&id) {
+            n += 1;
+            id = format!("{base}-{n}");
+        }
+        id
+    }
+
+    /// Stores a run, and then removes the runs that are past the
+    /// retention limits.
+    pub fn push<SI: SysApi>(
+        &mut self,
+        sys: &mut SI,
+        start: Instant,
+        end: Instant,
+        sts: &ExitSts,
+        stdout: &[String],
+        stderr: &[String],
+    ) -> io::Result<()> {
+        let id = self.new_id(&end);
+        let duration = &end - &start;
+        let metadata = json!({
+            "id": id,
+            "command": self.cmd.to_string(),
+            "start": start.format(TIMESTAMP, true),
+            "end": end.format(TIMESTAMP, true),
+            "duration": duration.num_milliseconds() as f64 / 1000.0,
+            "exit": sts.to_string(),
+        });
+        sys.write_file(&self.path(&id, "out"), &lines(stdout))?;
+        if !stderr.is_empty() {
+            sys.write_file(&self.path(&id, "err"), &lines(stderr))?;
+        }
+        sys.write_file(&self.path(&id, "json"), &format!("{metadata}\n"))?;
+        self.entries.push_back(Entry { id, end, metadata });
+        self.prune(sys, &end)?;
+        let index = (self.entries.iter())
+            .map(|entry| format!("{}\n", entry.metadata))
+            .collect::<String>();
+        sys.write_file(&self.dir.join(INDEX), &index)
+    }
+
+    /// Removes the runs that are past the retention limits.
+    fn prune<SI: SysApi>(&mut self, sys: &mut SI, now: &Instant) -> io::Result<()> {
+        loop {
+            let Some(oldest) = self.entries.front() else {
+                return Ok(());
+            };
+            let too_many = (self.retention.max_runs).is_some_and(|max| self.entries.len() > max);
+            let too_old = (self.retention.max_age).is_some_and(|max| now - &oldest.end > max);
+            if !too_many && !too_old {
+                return Ok(());
+            }
+            for extension in ["out", "err", "json"] {
+                match sys.remove_file(&self.path(&oldest.id, extension)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            self.entries.pop_front();
+        }
+    }
+}
+
+fn lines(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{line}\n")).collect()
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sys::SysVirtual;
+    use std::path::Path;
+
+    fn push(history: &mut History, sys: &mut SysVirtual, out: &str) -> io::Result<()> {
+        let start = sys.now();
+        let end = sys.now();
+        let stdout = vec![out.to_owned()];
+        history.push(sys, start, end, &ExitSts::Success, &stdout, &[])
+    }
+
+    #[test]
+    fn test_push() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let dir = PathBuf::from("hist");
+        let cmd = Cmd::from(&["echo", "a"][..]);
+        let mut history = History::new(&sys, dir.clone(), cmd.clone(), Retention::default())?;
+        push(&mut history, &mut sys, "a")?;
+        history.push(
+            &mut sys,
+            Instant::default(),
+            &Instant::default() + &Duration::seconds(1),
+            &ExitSts::Code(2),
+            &[],
+            &["oops".to_owned()],
+        )?;
+        assert_eq!(
+            sys.files(),
+            vec![
+                Path::new("hist/19700101T000001.000Z-2.err"),
+                Path::new("hist/19700101T000001.000Z-2.json"),
+                Path::new("hist/19700101T000001.000Z-2.out"),
+                Path::new("hist/19700101T000001.000Z.json"),
+                Path::new("hist/19700101T000001.000Z.out"),
+                Path::new("hist/index.jsonl"),
+            ]
+        );
+        assert_eq!(
+            sys.read_file(Path::new("hist/19700101T000001.000Z.out"))?,
+            "a\n"
+        );
+        let metadata = sys.read_file(Path::new("hist/19700101T000001.000Z-2.json"))?;
+        assert_eq!(
+            serde_json::from_str::<Value>(&metadata)?,
+            json!({
+                "id": "19700101T000001.000Z-2",
+                "command": "echo a",
+                "start": "1970-01-01T00:00:00.000Z",
+                "end": "1970-01-01T00:00:01.000Z",
+                "duration": 1.0,
+                "exit": "code 2",
+            })
+        );
+        // The index is loaded when we reopen the history:
+        let history = History::new(&sys, dir, cmd, Retention::default())?;
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.entries[1].id, "19700101T000001.000Z-2");
+        Ok(())
+    }
+
+    #[test]
+    fn test_retention() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let retention = Retention {
+            max_runs: Some(3),
+            max_age: None,
+        };
+        let mut history = History::new(&sys, "h".into(), Cmd::default(), retention)?;
+        for i in 0..5 {
+            push(&mut history, &mut sys, &i.to_string())?;
+        }
+        let ids = history
+            .entries
+            .iter()
+            .map(|e| e.id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ids,
+            vec![
+                "19700101T000005.000Z",
+                "19700101T000007.000Z",
+                "19700101T000009.000Z"
+            ]
+        );
+        assert_eq!(sys.files().len(), 3 * 2 + 1);
+        let index = sys.read_file(Path::new("h/index.jsonl"))?;
+        assert_eq!(index.lines().count(), 3);
+        // Runs finish every 2s, so we keep the last 2 with a max age
+        // of 3s:
+        let retention = Retention {
+            max_runs: None,
+            max_age: Some(Duration::seconds(3)),
+        };
+        let mut history = History::new(&sys, "h".into(), Cmd::default(), retention)?;
+        push(&mut history, &mut sys, "x")?;
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(sys.files().len(), 2 * 2 + 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Structural comparison of JSON outputs.
+//!
+//! The output of each run is parsed, optionally reduced with a
+//! [`Selector`], and flattened into `path: value` leaves; runs are
+//! then compared leaf by leaf, which makes the comparison insensitive
+//! to formatting and key order. The paths are always the ones in the
+//! whole document, so that we can tell which of the selected values
+//! changed.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// A step of a [`Selector`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Step {
+    /// `.key`: the value of the key in an object.
+    Key(String),
+    /// `[N]`: the element at the index in an array.
+    Index(usize),
+    /// `[]`: all elements of an array, or all values of an object.
+    Each,
+}
+
+/// A simple path selector, like `.items[].status.phase`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Selector(Vec<Step>);
+
+impl Selector {
+    /// Returns the parts of `value` that the selector picks, with their
+    /// paths in `value`; missing keys and indexes are picked as `null`.
+    pub fn select(&self, value: &Value) -> Vec<(String, Value)> {
+        let mut selected = vec![];
+        select(&mut selected, String::new(), value, &self.0);
+        selected
+    }
+}
+
+fn select(selected: &mut Vec<(String, Value)>, path: String, value: &Value, steps: &[Step]) {
+    let Some((step, rest)) = steps.split_first() else {
+        selected.push((path, value.clone()));
+        return;
+    };
+    match step {
+        Step::Key(key) => {
+            let child = value.get(key).unwrap_or(&Value::Null);
+            let path = path_child(&path, &Value::from(key.as_str()));
+            select(selected, path, child, rest);
+        }
+        Step::Index(index) => {
+            let child = value.get(index).unwrap_or(&Value::Null);
+            select(
+                selected,
+                path_child(&path, &Value::from(*index)),
+                child,
+                rest,
+            );
+        }
+        Step::Each => match value {
+            Value::Array(array) => {
+                for (i, v) in array.iter().enumerate() {
+                    select(selected, path_child(&path, &Value::from(i)), v, rest);
+                }
+            }
+            Value::Object(object) => {
+                for (k, v) in object {
+                    select(
+                        selected,
+                        path_child(&path, &Value::from(k.as_str())),
+                        v,
+                        rest,
+                    );
+                }
+            }
+            _ => {}
+        },
+    }
+}
+
+impl FromStr for Selector {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || eyre!("invalid selector {s}, expected something like .items[].name");
+        if !s.starts_with(['.', '[']) {
+            return Err(invalid());
+        }
+        let mut steps = vec![];
+        let mut rest = s;
+        while !rest.is_empty() {
+            if let Some(r) = rest.strip_prefix('[') {
+                let (inside, r) = r.split_once(']').ok_or_else(invalid)?;
+                steps.push(if inside.is_empty() {
+                    Step::Each
+                } else {
+                    Step::Index(inside.parse().map_err(|_| invalid())?)
+                });
+                rest = r;
+            } else if let Some(r) = rest.strip_prefix('.') {
+                let end = r.find(['.', '[']).unwrap_or(r.len());
+                if end > 0 {
+                    steps.push(Step::Key(r[..end].to_owned()));
+                }
+                rest = &r[end..];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(Selector(steps))
+    }
+}
+
+/// Parses the lines of a run as a single JSON document.
+///
+/// An empty output is an empty document, and invalid JSON is kept as a
+/// string with the error, so that it shows up in the comparison.
+pub fn parse(lines: &[String]) -> Value {
+    let text = lines.join("\n");
+    if text.trim().is_empty() {
+        return Value::Null;
+    }
+    serde_json::from_str(&text).unwrap_or_else(|e| Value::String(format!("invalid JSON: {e}")))
+}
+
+/// Returns the path of the key or index, given the path of its parent,
+/// using the `jq` syntax.
+fn path_child(path: &str, child: &Value) -> String {
+    match child {
+        Value::String(key)
+            if !key.is_empty() && key.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+        {
+            format!("{path}.{key}")
+        }
+        _ if path.is_empty() => format!(".[{child}]"),
+        _ => format!("{path}[{child}]"),
+    }
+}
+
+/// Flattens the value at `path` into its leaves, with their paths.
+///
+/// Empty arrays and objects are leaves, and `null` at the root has no
+/// leaves.
+fn flatten(path: &str, value: &Value) -> Vec<(String, String)> {
+    fn helper(leaves: &mut Vec<(String, String)>, path: String, value: &Value) {
+        match value {
+            Value::Array(array) if !array.is_empty() => {
+                for (i, v) in array.iter().enumerate() {
+                    helper(leaves, path_child(&path, &Value::from(i)), v);
+                }
+            }
+            Value::Object(object) if !object.is_empty() => {
+                for (k, v) in object {
+                    helper(leaves, path_child(&path, &Value::from(k.as_str())), v);
+                }
+            }
+            _ => {
+                let path = if path.is_empty() {
+                    ".".to_owned()
+                } else {
+                    path
+                };
+                leaves.push((path, value.to_string()));
+            }
+        }
+    }
+    let mut leaves = vec![];
+    if !path.is_empty() || !value.is_null() {
+        helper(&mut leaves, path.to_owned(), value);
+    }
+    leaves
+}
+
+/// Returns the leaves of the document, or of the parts of it that the
+/// selector picks, with their paths in the document.
+pub fn leaves(value: &Value, selector: Option<&Selector>) -> Vec<(String, String)> {
+    match selector {
+        // An empty output has nothing to select:
+        Some(selector) if !value.is_null() => (selector.select(value).iter())
+            .flat_map(|(path, v)| flatten(path, v))
+            .collect(),
+        Some(_) => vec![],
+        None => flatten("", value),
+    }
+}
+
+/// Compares the leaves of two documents, returning the `changed`,
+/// `added` and `removed` entries.
+pub fn diff(old: &[(String, String)], new: &[(String, String)]) -> Vec<String> {
+    let old_map = old.iter().cloned().collect::<HashMap<_, _>>();
+    let new_map = new.iter().cloned().collect::<HashMap<_, _>>();
+    let mut entries = vec![];
+    for (path, value) in new {
+        match old_map.get(path) {
+            Some(old_value) if old_value == value => {}
+            Some(old_value) => entries.push(format!("changed {path}: {old_value} → {value}")),
+            None => entries.push(format!("added {path}: {value}")),
+        }
+    }
+    for (path, value) in old {
+        if !new_map.contains_key(path) {
+            entries.push(format!("removed {path}: {value}"));
+        }
+    }
+    entries
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_selector() -> Result<()> {
+        let value = json!({"items": [
+            {"name": "a", "status": {"phase": "Running"}},
+            {"name": "b", "status": {"phase": "Pending"}},
+        ]});
+        let selector = Selector::from_str(".items[].status.phase")?;
+        assert_eq!(
+            selector.select(&value),
+            vec![
+                (".items[0].status.phase".to_owned(), json!("Running")),
+                (".items[1].status.phase".to_owned(), json!("Pending")),
+            ]
+        );
+        let selector = Selector::from_str(".items[1].name")?;
+        assert_eq!(
+            selector.select(&value),
+            vec![(".items[1].name".to_owned(), json!("b"))]
+        );
+        assert_eq!(
+            Selector::from_str(".")?.select(&value),
+            vec![(String::new(), value.clone())]
+        );
+        assert_eq!(
+            Selector::from_str(".x.y")?.select(&value),
+            vec![(".x.y".to_owned(), Value::Null)]
+        );
+        assert_eq!(
+            Selector::from_str(".items[0].name[]")?.select(&value),
+            vec![]
+        );
+        assert!(Selector::from_str("items").is_err());
+        assert!(Selector::from_str(".items[x]").is_err());
+        assert!(Selector::from_str(".items[").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse() {
+        let lines = vec!["{\"b\": 1,".to_owned(), "\"a\": [true]}".to_owned()];
+        assert_eq!(parse(&lines), json!({"a": [true], "b": 1}));
+        assert_eq!(parse(&[]), Value::Null);
+        assert!(matches!(parse(&["{".to_owned()]), Value::String(_)));
+    }
+
+    #[test]
+    fn test_flatten() {
+        let value = json!({"a": {"b c": [1, {}]}, "d": null});
+        assert_eq!(
+            flatten("", &value),
+            vec![
+                (".a[\"b c\"][0]".to_owned(), "1".to_owned()),
+                (".a[\"b c\"][1]".to_owned(), "{}".to_owned()),
+                (".d".to_owned(), "null".to_owned()),
+            ]
+        );
+        assert_eq!(
+            flatten("", &json!(3)),
+            vec![(".".to_owned(), "3".to_owned())]
+        );
+        assert_eq!(
+            flatten("", &json!([{"": 1}])),
+            vec![(".[0][\"\"]".to_owned(), "1".to_owned())]
+        );
+        assert_eq!(flatten("", &Value::Null), vec![]);
+        assert_eq!(
+            flatten(".a", &json!({"b": null})),
+            vec![(".a.b".to_owned(), "null".to_owned())]
+        );
+        assert_eq!(
+            flatten(".a", &Value::Null),
+            vec![(".a".to_owned(), "null".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = json!({"a": 1, "b": "x", "c": [1, 2]});
+        let new = json!({"c": [1], "b": "y", "a": 1, "d": true});
+        let old = leaves(&old, None);
+        let new = leaves(&new, None);
+        assert_eq!(
+            diff(&old, &new),
+            vec![
+                "changed .b: \"x\" → \"y\"",
+                "added .d: true",
+                "removed .c[1]: 2",
+            ]
+        );
+        assert_eq!(diff(&new, &new), Vec::<String>::new());
+        assert_eq!(
+            diff(&leaves(&Value::Null, None), &leaves(&json!({"a": 1}), None)),
+            vec!["added .a: 1"]
+        );
+    }
+
+    #[test]
+    fn test_diff_selected() -> Result<()> {
+        let selector = Selector::from_str(".items[].phase")?;
+        let old = json!({"items": [{"phase": "A"}, {"phase": "B"}]});
+        let new = json!({"items": [{"phase": "A", "x": 1}, {"phase": "C"}]});
+        assert_eq!(
+            diff(
+                &leaves(&old, Some(&selector)),
+                &leaves(&new, Some(&selector))
+            ),
+            vec!["changed .items[1].phase: \"B\" → \"C\""]
+        );
+        assert_eq!(leaves(&Value::Null, Some(&selector)), vec![]);
+        Ok(())
+    }
+}
@@ -59,35 +59,57 @@
 //!   - [`user_wrapper`]: abstract user interaction. At the moment, we
 //!     just monitor `stdin` in line mode, and ogle exits gracefully
 //!     when that's detected.
-//!   - [`time_wrapper`]: home of the
-//!     [`Instant`](time_wrapper::Instant) and
-//!     [`Duration`](time_wrapper::Duration) types, which use types
-//!     from [`chrono`] at the moment.
+//!   - [`time_wrapper`]: home of the [`time_wrapper::Instant`] and
+//!     [`time_wrapper::Duration`] types, which use types from
+//!     [`chrono`] at the moment.
 //! - [`sys`]: most of the ogle code doesn't really call functions
 //!   that interact with the host system - we have the `sys` module
 //!   for that. The module does that by providing a [`sys::SysApi`]
 //!   trait that is then implemented by both the [`sys::SysReal`]
 //!   type, which calls the system functions; and by the
 //!   [`sys::SysVirtual`] type, which can be used to mock these calls
-//!   in various ways.
+//!   in various ways. [`sys::SysReplay`] implements it for the
+//!   replay of recorded sessions.
 //!
 //! ```no_compile
 //! sys -> engine -> view -> output
 //! ```
 //!
+//! # Library
+//!
+//! With the `lib` feature, other programs can use the lower layers:
+//! [`engine::EngineBuilder`] creates an [`engine::Engine`], a stream
+//! of [`engine::EItem`]s that has the lines and exit status of each
+//! run, and [`differ::Differ`] compares the lines of the runs the same
+//! way ogle does.
+//! The `examples` directory has programs that consume the stream.
+//!
 //! [watch (1)]: https://linux.die.net/man/1/watch
 //!
 
 use clap::Parser;
+use color_eyre::eyre::WrapErr;
 use std::error::Error;
 
 #[macro_use]
 mod misc;
 
+mod alert;
+mod asciicast;
+mod change;
 mod cli;
 mod differ;
+mod history;
+mod json;
+mod numeric;
 mod orchestrator;
 mod progbar;
+mod record;
+mod snapshot;
+mod summary;
+mod table;
+mod template;
+mod track;
 
 mod process_wrapper;
 mod term_wrapper;
@@ -102,7 +124,37 @@ mod view;
 
 mod output;
 
-/// Ogle main function, the single pub function in this lib.
+#[cfg(feature = "lib")]
+pub use differ::Differ;
+#[cfg(feature = "lib")]
+pub use differ::Mode;
+#[cfg(feature = "lib")]
+pub use engine::EData;
+#[cfg(feature = "lib")]
+pub use engine::EItem;
+#[cfg(feature = "lib")]
+pub use engine::Engine;
+#[cfg(feature = "lib")]
+pub use engine::EngineBuilder;
+#[cfg(feature = "lib")]
+pub use engine::ExitConditions;
+#[cfg(feature = "lib")]
+pub use json::Selector;
+#[cfg(feature = "lib")]
+pub use numeric::Tolerance;
+#[cfg(feature = "lib")]
+pub use process_wrapper::Cmd;
+#[cfg(feature = "lib")]
+pub use process_wrapper::ExitSts;
+#[cfg(feature = "lib")]
+pub use sys::SysReal;
+#[cfg(feature = "lib")]
+pub use time_wrapper::Duration;
+#[cfg(feature = "lib")]
+pub use time_wrapper::Instant;
+
+/// Ogle main function, the single pub function in this lib without
+/// the `lib` feature.
 #[tokio::main(flavor = "current_thread")]
 pub async fn main() -> Result<(), Box<dyn Error>> {
     color_eyre::install()?;
@@ -111,8 +163,20 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
         .with_span_events(tracing_subscriber::fmt::format::FmtSpan::ACTIVE)
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
-    let args = cli::Cli::parse();
-    let sys = sys::SysReal::default();
-    orchestrator::run(args, sys).await?;
+    let mut args = cli::Cli::parse();
+    match args.action.take() {
+        Some(cli::Action::Replay { file, speed }) => {
+            let recording = record::Recording::load(&sys::SysReal::default(), &file)
+                .wrap_err_with(|| format!("error reading {}", file.display()))?;
+            args.command = recording.cmd.args().to_vec();
+            args.max_concurrent = recording.max_concurrent;
+            let sys = sys::SysReplay::new(recording.items, speed);
+            orchestrator::run(args, sys).await?;
+        }
+        None => {
+            let sys = sys::SysReal::default();
+            orchestrator::run(args, sys).await?;
+        }
+    }
     Ok(())
 }
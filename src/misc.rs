@@ -4,29 +4,69 @@
 
 //! Misc utility functions
 
+use color_eyre::Result;
+
+use crate::template::Template;
 use crate::time_wrapper::Instant;
+use crate::time_wrapper::TimeFormat;
+
+/// The default prefix of the lines that `ogle` prints.
+pub const DEFAULT_PREFIX: &str = "<O> {time}";
+
+/// Placeholders of the prefix template.
+pub const PREFIX_PLACEHOLDERS: &[&str] = &["time"];
 
-pub fn ofmt_helper(timestamp: &Instant, line: &str) -> String {
-    format!("<O> {timestamp} {line}")
+/// Configuration of the standard `ogle` format.
+#[derive(Debug, Clone)]
+pub struct Ofmt {
+    pub time: TimeFormat,
+    /// Template of the prefix, with the `{time}` placeholder.
+    pub prefix: Template,
+}
+
+impl Ofmt {
+    pub fn new(time: TimeFormat, prefix: &str) -> Result<Self> {
+        let prefix = Template::new(prefix, PREFIX_PLACEHOLDERS)?;
+        Ok(Self { time, prefix })
+    }
+
+    fn format(&self, time: Option<String>, line: &str) -> String {
+        let prefix = self.prefix.render(|_| time.clone().unwrap_or_default());
+        if prefix.is_empty() {
+            line.to_owned()
+        } else {
+            format!("{prefix} {line}")
+        }
+    }
+
+    pub fn helper(&self, timestamp: &Instant, line: &str) -> String {
+        self.format(self.time.format(timestamp), line)
+    }
+
+    pub fn timeless_helper(&self, line: &str) -> String {
+        self.format(None, line)
+    }
+}
+
+impl Default for Ofmt {
+    fn default() -> Self {
+        Self::new(TimeFormat::default(), DEFAULT_PREFIX).expect("invalid default prefix")
+    }
 }
 
 /// Print a message on stdout, with a timestamp, in the standard
-/// `ogle` format
+/// `ogle` format configured by the given [`Ofmt`]
 macro_rules! ofmt {
-    ($timestamp: expr, $($t:tt)*) => {{
-        crate::misc::ofmt_helper($timestamp, &format!($($t)*))
+    ($ofmt: expr, $timestamp: expr, $($t:tt)*) => {{
+        $ofmt.helper($timestamp, &format!($($t)*))
     }};
 }
 
-pub fn ofmt_timeless_helper(line: &str) -> String {
-    format!("<O> {line}")
-}
-
 /// Print a message on stdout, without a timestamp, in the standard
-/// `ogle` format
+/// `ogle` format configured by the given [`Ofmt`]
 macro_rules! ofmt_timeless {
-    ($($t:tt)*) => {{
-        crate::misc::ofmt_timeless_helper(&format!($($t)*))
+    ($ofmt: expr, $($t:tt)*) => {{
+        $ofmt.timeless_helper(&format!($($t)*))
     }};
 }
 
@@ -34,13 +74,19 @@ macro_rules! ofmt_timeless {
 mod tests {
     use super::*;
     use crate::time_wrapper::Instant;
-    use color_eyre::Result;
+
+    /// The default format, but in UTC, like the [`Instant`] `Display`
+    /// in tests.
+    fn ofmt_utc(prefix: &str) -> Result<Ofmt> {
+        let time = TimeFormat::new("%Y-%m-%d %H:%M:%S", true, Instant::default())?;
+        Ofmt::new(time, prefix)
+    }
 
     #[test]
     fn test_ofmt_helper() -> Result<()> {
         let timestamp = Instant::default();
         assert_eq!(
-            ofmt_helper(&timestamp, "test line"),
+            ofmt_utc(DEFAULT_PREFIX)?.helper(&timestamp, "test line"),
             format!("<O> {} test line", timestamp)
         );
         Ok(())
@@ -49,21 +95,62 @@ mod tests {
     #[test]
     fn test_ofmt_macro() -> Result<()> {
         let timestamp = Instant::default();
-        let formatted = ofmt!(&timestamp, "hello {}", 123);
+        let formatted = ofmt!(ofmt_utc(DEFAULT_PREFIX)?, &timestamp, "hello {}", 123);
         assert_eq!(formatted, format!("<O> {} hello 123", timestamp));
         Ok(())
     }
 
     #[test]
     fn test_ofmt_timeless_helper() -> Result<()> {
-        assert_eq!(ofmt_timeless_helper("timeless test"), "<O> timeless test");
+        let ofmt = Ofmt::default();
+        assert_eq!(ofmt.timeless_helper("timeless test"), "<O> timeless test");
         Ok(())
     }
 
     #[test]
     fn test_ofmt_timeless_macro() -> Result<()> {
-        let formatted = ofmt_timeless!("value: {}", 42.5);
+        let formatted = ofmt_timeless!(Ofmt::default(), "value: {}", 42.5);
         assert_eq!(formatted, "<O> value: 42.5");
         Ok(())
     }
+
+    #[test]
+    fn test_ofmt_time_format() -> Result<()> {
+        let origin = Instant::default();
+        let timestamp = &origin + &crate::time_wrapper::Duration::milliseconds(1500);
+        let ofmt = |format: &str| -> Result<Ofmt> {
+            Ofmt::new(TimeFormat::new(format, true, origin)?, DEFAULT_PREFIX)
+        };
+        assert_eq!(
+            ofmt!(ofmt("relative")?, &timestamp, "hello"),
+            "<O> +1.500s hello"
+        );
+        assert_eq!(
+            ofmt!(ofmt("time")?, &timestamp, "hello"),
+            "<O> 00:00:01 hello"
+        );
+        assert_eq!(
+            ofmt!(ofmt("iso")?, &timestamp, "hello"),
+            "<O> 1970-01-01T00:00:01+00:00 hello"
+        );
+        assert_eq!(ofmt!(ofmt("none")?, &timestamp, "hello"), "<O> hello");
+        Ok(())
+    }
+
+    #[test]
+    fn test_ofmt_prefix() -> Result<()> {
+        let timestamp = Instant::default();
+        assert_eq!(
+            ofmt!(ofmt_utc("[ogle {time}]")?, &timestamp, "hello {}", 123),
+            "[ogle 1970-01-01 00:00:00] hello 123"
+        );
+        assert_eq!(
+            ofmt_timeless!(ofmt_utc("[ogle {time}]")?, "hello"),
+            "[ogle] hello"
+        );
+        assert_eq!(ofmt!(ofmt_utc("")?, &timestamp, "hello"), "hello");
+        assert_eq!(ofmt_timeless!(ofmt_utc("")?, "hello"), "hello");
+        assert!(Ofmt::new(TimeFormat::default(), "{date}").is_err());
+        Ok(())
+    }
 }
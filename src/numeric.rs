@@ -0,0 +1,290 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Handling of the numbers found in the lines of the output.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use regex::Regex;
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use crate::time_wrapper::Instant;
+
+static NUMBER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"-?\d+(\.\d+)?").expect("invalid number regex"));
+
+/// Placeholder for the numbers in the skeleton of a line.
+const PLACEHOLDER: &str = "\u{0}";
+
+/// Returns the ranges of the numbers in the line.
+///
+/// A `-` after a word character is a separator, as in `web-1` or
+/// `2025-01-02`, and not the sign of the number.
+fn number_ranges(line: &str) -> impl Iterator<Item = Range<usize>> + '_ {
+    NUMBER.find_iter(line).map(|m| {
+        let mut range = m.range();
+        let after_word = line[..range.start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_alphanumeric() || c == '_');
+        if after_word && m.as_str().starts_with('-') {
+            range.start += 1;
+        }
+        range
+    })
+}
+
+/// Splits the line in its skeleton, which is the line with the
+/// numbers replaced by a placeholder, and the numbers themselves.
+pub fn split(line: &str) -> (String, Vec<f64>) {
+    let mut skeleton = String::new();
+    let mut numbers = vec![];
+    let mut last = 0;
+    for range in number_ranges(line) {
+        skeleton.push_str(&line[last..range.start]);
+        skeleton.push_str(PLACEHOLDER);
+        numbers.extend(line[range.clone()].parse::<f64>().ok());
+        last = range.end;
+    }
+    skeleton.push_str(&line[last..]);
+    (skeleton, numbers)
+}
+
+/// A number found in a line.
+#[derive(Debug, Clone, PartialEq)]
+struct Number {
+    range: Range<usize>,
+    value: f64,
+    decimals: usize,
+}
+
+fn numbers(line: &str) -> Vec<Number> {
+    number_ranges(line)
+        .filter_map(|range| {
+            let number = &line[range.clone()];
+            Some(Number {
+                range,
+                value: number.parse().ok()?,
+                decimals: number.split_once('.').map_or(0, |(_, d)| d.len()),
+            })
+        })
+        .collect()
+}
+
+// Deltas ////////////////////////////////////////////////////////////
+
+/// Annotates the numbers in the lines of a run with how much they
+/// changed since the previous run, and the corresponding rate.
+///
+/// Lines are matched with the lines of the previous run by position,
+/// and are only annotated if they have the same skeleton.
+#[derive(Debug, Default)]
+pub struct Deltas {
+    previous_start: Option<Instant>,
+    previous: Vec<String>,
+    start: Option<Instant>,
+    current: Vec<String>,
+    /// Index of the next line to annotate.
+    iline: usize,
+}
+
+impl Deltas {
+    /// Starts a new run, making the current one the previous.
+    pub fn reset(&mut self, start: Instant) {
+        self.previous_start = self.start.replace(start);
+        self.previous = std::mem::take(&mut self.current);
+        self.iline = 0;
+    }
+
+    pub fn push(&mut self, line: &str) {
+        self.current.push(line.to_owned());
+    }
+
+    /// Annotates the next line of the current run; the lines must be
+    /// annotated in the order they were pushed.
+    pub fn annotate(&mut self, line: String) -> String {
+        let iline = self.iline;
+        self.iline += 1;
+        let Some(previous) = self.previous.get(iline) else {
+            return line;
+        };
+        if split(previous).0 != split(&line).0 {
+            return line;
+        }
+        let seconds = match (self.previous_start, self.start) {
+            (Some(previous_start), Some(start)) => {
+                (&start - &previous_start).num_milliseconds() as f64 / 1000.0
+            }
+            _ => 0.0,
+        };
+        let mut annotated = String::new();
+        let mut last = 0;
+        for (old, new) in numbers(previous).into_iter().zip(numbers(&line)) {
+            if old.value == new.value {
+                continue;
+            }
+            let delta = new.value - old.value;
+            let decimals = old.decimals.max(new.decimals);
+            annotated.push_str(&line[last..new.range.end]);
+            if seconds > 0.0 {
+                annotated.push_str(&format!(
+                    " ({:+.*}, {:+.1}/s)",
+                    decimals,
+                    delta,
+                    delta / seconds
+                ));
+            } else {
+                annotated.push_str(&format!(" ({:+.*})", decimals, delta));
+            }
+            last = new.range.end;
+        }
+        annotated.push_str(&line[last..]);
+        annotated
+    }
+}
+
+// Tolerance /////////////////////////////////////////////////////////
+
+/// How much the numbers in corresponding lines can differ before we
+/// consider the lines different.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tolerance {
+    /// Maximum absolute difference.
+    Absolute(f64),
+    /// Maximum difference relative to the largest of the numbers.
+    Relative(f64),
+}
+
+impl Tolerance {
+    fn number_matches(&self, a: f64, b: f64) -> bool {
+        let diff = (a - b).abs();
+        match self {
+            Tolerance::Absolute(max) => diff <= *max,
+            Tolerance::Relative(ratio) => diff <= ratio * a.abs().max(b.abs()),
+        }
+    }
+
+    /// Returns true if the lines have the same text around the numbers
+    /// and all numbers are within the tolerance.
+    pub fn matches(&self, a: &str, b: &str) -> bool {
+        if a == b {
+            return true;
+        }
+        let (skeleton_a, numbers_a) = split(a);
+        let (skeleton_b, numbers_b) = split(b);
+        skeleton_a == skeleton_b
+            && numbers_a.len() == numbers_b.len()
+            && numbers_a
+                .iter()
+                .zip(numbers_b.iter())
+                .all(|(a, b)| self.number_matches(*a, *b))
+    }
+}
+
+impl FromStr for Tolerance {
+    type Err = color_eyre::Report;
+
+    /// Parses `5%` as a relative tolerance and `0.5` as an absolute
+    /// one.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || eyre!("invalid tolerance {s}");
+        let tolerance = if let Some(percent) = s.strip_suffix('%') {
+            let percent = percent.trim().parse::<f64>().map_err(|_| invalid())?;
+            Tolerance::Relative(percent / 100.0)
+        } else {
+            Tolerance::Absolute(s.trim().parse::<f64>().map_err(|_| invalid())?)
+        };
+        match tolerance {
+            Tolerance::Absolute(v) | Tolerance::Relative(v) if v.is_finite() && v >= 0.0 => {
+                Ok(tolerance)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let (skeleton, numbers) = split("load 0.52 1.04, up 3 days");
+        assert_eq!(skeleton, "load \u{0} \u{0}, up \u{0} days");
+        assert_eq!(numbers, vec![0.52, 1.04, 3.0]);
+        let (skeleton, numbers) = split("temp -5 at web-1 on 2025-01-02");
+        assert_eq!(skeleton, "temp \u{0} at web-\u{0} on \u{0}-\u{0}-\u{0}");
+        assert_eq!(numbers, vec![-5.0, 1.0, 2025.0, 1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let n = numbers("a 12 b 0.50");
+        assert_eq!(n[0].range, 2..4);
+        assert_eq!((n[1].value, n[1].decimals), (0.5, 2));
+        let n = numbers("a -12 b-3");
+        assert_eq!((n[0].range.clone(), n[0].value), (2..5, -12.0));
+        assert_eq!((n[1].range.clone(), n[1].value), (8..9, 3.0));
+    }
+
+    #[test]
+    fn test_deltas() {
+        let mut start = Instant::default();
+        let mut deltas = Deltas::default();
+        deltas.reset(start.incr());
+        deltas.push("eth0: 100 7");
+        deltas.push("lo: 5");
+        assert_eq!(deltas.annotate("eth0: 100 7".to_owned()), "eth0: 100 7");
+        // Runs start 2 seconds apart:
+        start.incr();
+        deltas.reset(start.incr());
+        deltas.push("eth0: 150 7");
+        deltas.push("lo 9");
+        assert_eq!(
+            deltas.annotate("eth0: 150 7".to_owned()),
+            "eth0: 150 (+50, +25.0/s) 7"
+        );
+        // Different skeleton:
+        assert_eq!(deltas.annotate("lo 9".to_owned()), "lo 9");
+        deltas.reset(start.incr());
+        assert_eq!(
+            deltas.annotate("eth0: 149.5 7".to_owned()),
+            "eth0: 149.5 (-0.5, -0.5/s) 7"
+        );
+        deltas.push("temp -5");
+        deltas.reset(start.incr());
+        assert_eq!(
+            deltas.annotate("temp 5".to_owned()),
+            "temp 5 (+10, +10.0/s)"
+        );
+    }
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        assert_eq!(Tolerance::from_str("5%")?, Tolerance::Relative(0.05));
+        assert_eq!(Tolerance::from_str("0.5")?, Tolerance::Absolute(0.5));
+        assert!(Tolerance::from_str("x%").is_err());
+        assert!(Tolerance::from_str("-1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_matches() {
+        let relative = Tolerance::Relative(0.05);
+        assert!(relative.matches("mem 100 MB", "mem 104 MB"));
+        assert!(!relative.matches("mem 100 MB", "mem 110 MB"));
+        assert!(!relative.matches("mem 100 MB", "mem 100 GB"));
+        assert!(!relative.matches("mem 100 MB", "mem 100 MB 1"));
+        let absolute = Tolerance::Absolute(0.5);
+        assert!(absolute.matches("load 1.2", "load 1.6"));
+        assert!(!absolute.matches("load 1.2", "load 1.8"));
+        assert!(absolute.matches("temp -5.2", "temp -5"));
+        assert!(!absolute.matches("temp -5", "temp 5"));
+        assert!(!relative.matches("temp -5", "temp 5"));
+    }
+}
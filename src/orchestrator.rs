@@ -3,29 +3,274 @@
 // file 'LICENSE', which is part of this source code package.
 
 use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
+use color_eyre::eyre::eyre;
+use regex::Regex;
+use std::str::FromStr;
 use tracing::instrument;
 
+use crate::alert::Alert;
+use crate::asciicast::Asciicast;
+use crate::change::ChangePolicy;
 use crate::cli::Cli;
-use crate::engine::Engine;
+use crate::cli::Format;
+use crate::differ;
+use crate::engine::EngineBuilder;
+use crate::engine::ExitConditions;
+use crate::history::History;
+use crate::history::Retention;
+use crate::json::Selector;
+use crate::misc::Ofmt;
 use crate::output::output;
+use crate::output::output_asciicast;
 use crate::process_wrapper::Cmd;
+use crate::progbar::STATUS_PLACEHOLDERS;
+use crate::snapshot::Snapshot;
 use crate::sys::SysApi;
+use crate::template::Template;
 use crate::time_wrapper::Duration;
+use crate::time_wrapper::TimeFormat;
+use crate::track::Tracker;
+use crate::view::Options as ViewOptions;
 use crate::view::View;
 
 #[instrument(level = "debug")]
-pub async fn run<SI: SysApi>(cli: Cli, sys: SI) -> Result<()> {
+pub async fn run<SI: SysApi>(cli: Cli, mut sys: SI) -> Result<()> {
+    let (view, asciicast) = view(cli, &mut sys)?;
+    match asciicast {
+        Some(asciicast) => output_asciicast(view, sys, asciicast).await,
+        None => output(view).await,
+    }
+}
+
+/// Creates the view of the command with the options of the command
+/// line, along with the asciicast that records it, if requested.
+fn view<SI: SysApi>(cli: Cli, sys: &mut SI) -> Result<(View<SI>, Option<Asciicast>)> {
     let refresh = Duration::milliseconds(250);
     let sleep = Duration::seconds(cli.period.into());
+    let max_concurrent = usize::try_from(cli.max_concurrent)?;
     let cmd = Cmd::from(cli.command);
-    let engine = Engine::new(
-        sys.clone(),
-        cmd.clone(),
-        refresh,
-        sleep,
-        cli.until_success,
-        cli.until_failure,
-    )?;
-    let view = View::new(cmd, refresh, sleep, engine);
-    output(view).await
+    let exit = ExitConditions {
+        success: cli.until_success,
+        failure: cli.until_failure,
+        matching: (cli.until_match.as_deref().map(Regex::new).transpose())
+            .wrap_err("invalid --until-match regex")?,
+        not_matching: (cli.until_no_match.as_deref().map(Regex::new).transpose())
+            .wrap_err("invalid --until-no-match regex")?,
+    };
+    let expect = match cli.expect {
+        Some(path) => {
+            let contents = sys
+                .read_file(&path)
+                .wrap_err_with(|| format!("error reading {}", path.display()))?;
+            Some(contents.lines().map(String::from).collect())
+        }
+        None => None,
+    };
+    let tracker = (cli.track.as_deref().map(Regex::new).transpose())
+        .wrap_err("invalid --track regex")?
+        .map(Tracker::new)
+        .transpose()?;
+    let alert = match cli.alert {
+        Some(condition) => {
+            let mut alert = Alert::from_str(&condition)?;
+            let name = tracker.as_ref().and_then(Tracker::name);
+            if name != Some(alert.name.as_str()) {
+                return Err(eyre!(
+                    "--alert refers to {}, which must be the name of the --track capture group, as in (?<{}>...)",
+                    alert.name,
+                    alert.name
+                ));
+            }
+            alert.bell = cli.alert_bell;
+            alert.hook = cli.alert_hook;
+            alert.exit = cli.alert_exit;
+            Some(alert)
+        }
+        None => None,
+    };
+    let mode = match cli.format {
+        Format::Json | Format::Table
+            if cli.unordered
+                || cli.sort
+                || cli.delta
+                || cli.line_timestamps.is_some()
+                || cli.tolerance.is_some() =>
+        {
+            return Err(eyre!(
+                "--format json and table can't be used with --unordered, --sort, --delta, --line-timestamps or --tolerance"
+            ));
+        }
+        _ if cli.select.is_some() && cli.format != Format::Json => {
+            return Err(eyre!("--select requires --format json"));
+        }
+        _ if cli.key_column.is_some() && cli.format != Format::Table => {
+            return Err(eyre!("--key-column requires --format table"));
+        }
+        Format::Json => differ::Mode::Json,
+        Format::Table => differ::Mode::Table,
+        Format::Lines if cli.unordered => differ::Mode::Unordered,
+        Format::Lines if cli.sort => differ::Mode::Sorted,
+        Format::Lines => differ::Mode::Lines,
+    };
+    let time = TimeFormat::new(&cli.time_format, cli.utc, sys.now())?;
+    let history = match cli.history_dir {
+        Some(dir) => {
+            let retention = Retention {
+                max_runs: cli.history_max_runs.map(usize::try_from).transpose()?,
+                max_age: cli.history_max_age.map(|age| Duration::seconds(age.into())),
+            };
+            let history = History::new(sys, dir.clone(), cmd.clone(), retention)
+                .wrap_err_with(|| format!("error reading history in {}", dir.display()))?;
+            Some(history)
+        }
+        None => None,
+    };
+    let resume = match &cli.state_file {
+        Some(path) => {
+            let snapshot = Snapshot::load(sys, path)
+                .wrap_err_with(|| format!("error reading {}", path.display()))?;
+            match snapshot {
+                Some(snapshot) if snapshot.command != cmd.to_string() => {
+                    return Err(eyre!(
+                        "state file {} is for the command {}, remove it to start over",
+                        path.display(),
+                        snapshot.command
+                    ));
+                }
+                snapshot => snapshot,
+            }
+        }
+        None => None,
+    };
+    let selector = cli.select.as_deref().map(Selector::from_str).transpose()?;
+    let options = ViewOptions {
+        max_concurrent,
+        compare: cli.compare,
+        policy: ChangePolicy::new(&cli.change_on),
+        mode,
+        selector,
+        key_column: cli.key_column,
+        tolerance: (cli.tolerance.as_deref().map(str::parse).transpose())
+            .wrap_err("invalid --tolerance")?,
+        delta: cli.delta,
+        tracker,
+        alert,
+        baseline: cli.baseline,
+        expect,
+        until_expected: cli.until_expected,
+        until_stable: cli.until_stable,
+        line_timestamps: cli.line_timestamps,
+        ofmt: Ofmt::new(time, &cli.prefix).wrap_err("invalid --prefix")?,
+        status: (cli.status.as_deref())
+            .map(|status| Template::new(status, STATUS_PLACEHOLDERS))
+            .transpose()
+            .wrap_err("invalid --status")?,
+        summary: cli.summary,
+        history,
+        state_file: cli.state_file,
+        resume,
+    };
+    let asciicast = match cli.asciicast {
+        Some(path) => Some(
+            Asciicast::new(sys, path.clone(), &cmd)
+                .wrap_err_with(|| format!("error creating asciicast {}", path.display()))?,
+        ),
+        None => None,
+    };
+    // The engine creates the --record file, so we build it after
+    // checking all other options:
+    let engine = EngineBuilder::new(cmd.clone())
+        .with_refresh(refresh)
+        .with_period(sleep)
+        .with_max_concurrent(max_concurrent)
+        .with_exit(exit)
+        .with_filter(
+            cli.filter
+                .map(|filter| Cmd::from(vec!["sh".to_owned(), "-c".to_owned(), filter])),
+        )
+        .with_record(cli.record)
+        .with_user_input(true)
+        .build(sys.clone())?;
+    let view = View::new(cmd, refresh, sleep, options, engine);
+    Ok((view, asciicast))
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+    use std::path::Path;
+    use tokio_stream::StreamExt;
+
+    use crate::output::OutputCommand;
+    use crate::output::WriteAll;
+    use crate::process_wrapper::ExitSts;
+    use crate::sys::Script;
+    use crate::sys::SysVirtual;
+
+    /// Returns the lines written by the view of the command line
+    /// `args`, run with `sys`.
+    async fn written(args: &[&str], mut sys: SysVirtual) -> Result<Vec<String>> {
+        let cli = Cli::try_parse_from(args)?;
+        let (view, _) = view(cli, &mut sys)?;
+        Ok(view
+            .filter_map(|command| match command {
+                OutputCommand::WriteAll(WriteAll(bytes)) => {
+                    Some(String::from_utf8_lossy(&bytes).trim_end().to_owned())
+                }
+                _ => None,
+            })
+            .collect()
+            .await)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_until_expected() -> Result<()> {
+        let mut sys = SysVirtual::default().with_virtual_clock();
+        sys.write_file(Path::new("expected"), "b\n")?;
+        sys.set_runs(vec![
+            Script::default().out("a").exit(ExitSts::Success),
+            Script::default().out("b").exit(ExitSts::Success),
+            Script::default().out("c").exit(ExitSts::Success),
+        ]);
+        let args = [
+            "ogle",
+            "--expect",
+            "expected",
+            "--until-expected",
+            "--",
+            "cmd",
+        ];
+        let written = written(&args, sys).await?;
+        // The first run differs from the expected output, and we exit
+        // after the second one, which matches it:
+        assert!(written.contains(&"a".to_owned()));
+        assert!(!written.contains(&"c".to_owned()));
+        assert!(
+            (written.last())
+                .is_some_and(|line| line.ends_with("output matches the expected output"))
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expect_missing() {
+        let sys = SysVirtual::default().with_virtual_clock();
+        let args = ["ogle", "--expect", "expected", "--", "cmd"];
+        let error = written(&args, sys).await.unwrap_err();
+        assert_eq!(error.to_string(), "error reading expected");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_format_tolerance() {
+        for format in ["json", "table"] {
+            let sys = SysVirtual::default().with_virtual_clock();
+            let args = ["ogle", "--format", format, "--tolerance", "5%", "--", "cmd"];
+            let error = written(&args, sys).await.unwrap_err();
+            assert!(error.to_string().ends_with("--tolerance"));
+        }
+    }
 }
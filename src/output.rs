@@ -8,46 +8,78 @@
 //! to test that we are issuing the correct commands.
 
 use color_eyre::Result;
+use color_eyre::eyre::WrapErr;
 use enum_dispatch::enum_dispatch;
 use tokio_stream::Stream;
 use tokio_stream::StreamExt;
 
+use crate::asciicast::Asciicast;
+use crate::sys::SysApi;
 use crate::term_wrapper::*;
 
 #[enum_dispatch(OutputCommand)]
 pub trait OutputCommandTrait {
     fn execute(&self) -> Result<()>;
+    /// Returns the bytes that [`OutputCommandTrait::execute`] writes
+    /// to the terminal.
+    fn ansi(&self) -> Vec<u8>;
 }
 
+#[derive(Debug, PartialEq, Eq)]
 pub struct MoveCursorUp(pub u16);
 impl OutputCommandTrait for MoveCursorUp {
     fn execute(&self) -> Result<()> {
         move_cursor_up(self.0)?;
         Ok(())
     }
+    fn ansi(&self) -> Vec<u8> {
+        move_cursor_up_ansi(self.0)
+    }
 }
 
+#[derive(Debug, PartialEq, Eq)]
 pub struct ClearLine {}
 impl OutputCommandTrait for ClearLine {
     fn execute(&self) -> Result<()> {
         clear_line()?;
         Ok(())
     }
+    fn ansi(&self) -> Vec<u8> {
+        clear_line_ansi()
+    }
 }
 
+#[derive(Debug, PartialEq, Eq)]
 pub struct WriteAll(pub Vec<u8>);
 impl OutputCommandTrait for WriteAll {
     fn execute(&self) -> Result<()> {
         write_all(&self.0)?;
         Ok(())
     }
+    fn ansi(&self) -> Vec<u8> {
+        write_all_ansi(&self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SetColor(pub Option<Color>);
+impl OutputCommandTrait for SetColor {
+    fn execute(&self) -> Result<()> {
+        set_color(self.0)?;
+        Ok(())
+    }
+    fn ansi(&self) -> Vec<u8> {
+        set_color_ansi(self.0)
+    }
 }
 
 #[enum_dispatch]
+#[derive(Debug, PartialEq, Eq)]
 pub enum OutputCommand {
     MoveCursorUp,
     ClearLine,
     WriteAll,
+    SetColor,
 }
 
 /// This function runs all commands in the provided stream until it is
@@ -61,3 +93,16 @@ where
     }
     Ok(())
 }
+
+/// Like [`output`], but also records the commands in the `asciicast`.
+pub async fn output_asciicast<S, SI>(mut stream: S, mut sys: SI, asciicast: Asciicast) -> Result<()>
+where
+    S: Stream<Item = OutputCommand> + std::marker::Unpin,
+    SI: SysApi,
+{
+    while let Some(cmd) = stream.next().await {
+        (asciicast.record(&mut sys, &cmd)).wrap_err("error writing the asciicast")?;
+        cmd.execute()?;
+    }
+    Ok(())
+}
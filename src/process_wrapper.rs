@@ -32,12 +32,22 @@ use tokio_process_stream as tps;
 use tokio_stream::Stream;
 use tracing::instrument;
 
+use crate::time_wrapper::Duration;
+use crate::time_wrapper::Timeline;
+
 // Command wrapper ///////////////////////////////////////////////////
 
 /// A [`tokio::process::Command`] pseudo-wrapper that `impl Clone`.
 #[derive(Debug, Default, Clone)]
 pub struct Cmd(Vec<String>);
 
+impl Cmd {
+    /// Returns the program and its arguments.
+    pub fn args(&self) -> &[String] {
+        &self.0
+    }
+}
+
 impl From<&Cmd> for Command {
     fn from(cmd: &Cmd) -> Command {
         let mut command = Command::new(&cmd.0[0]);
@@ -74,13 +84,17 @@ impl fmt::Display for Cmd {
 /// and custom Display.
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
 pub enum ExitSts {
+    /// The process exited with code 0.
     #[default]
     Success,
+    /// The process exited with the provided non-zero code.
     Code(u8),
+    /// The process was terminated by the provided signal.
     Signal(i32),
 }
 
 impl ExitSts {
+    /// Returns true if the process exited with success.
     pub fn success(&self) -> bool {
         self == &ExitSts::Success
     }
@@ -153,8 +167,8 @@ pub enum ProcessStream {
     /// Wrapper for [`tokio_process_stream::ProcessLineStream`].
     Real { stream: Box<tps::ProcessLineStream> },
     /// Mock for a running process stream that just returns items from
-    /// a list. Useful for testing.
-    Virtual { items: VecDeque<Item> },
+    /// a list, each after its delay. Useful for testing.
+    Virtual { items: Timeline<Item> },
 }
 
 impl ProcessStream {
@@ -187,7 +201,15 @@ impl From<tps::ProcessLineStream> for ProcessStream {
 }
 
 impl From<VecDeque<Item>> for ProcessStream {
+    /// Yields the items without waiting.
     fn from(items: VecDeque<Item>) -> Self {
+        let items = items.into_iter().map(|item| (Duration::default(), item));
+        Self::from(Timeline::from(items.collect::<VecDeque<_>>()))
+    }
+}
+
+impl From<Timeline<Item>> for ProcessStream {
+    fn from(items: Timeline<Item>) -> Self {
         ProcessStream::Virtual { items }
     }
 }
@@ -206,7 +228,7 @@ impl Stream for ProcessStream {
                     Poll::Pending => Poll::Pending,
                 }
             }
-            ProcessStream::Virtual { items } => Poll::Ready(items.pop_front()),
+            ProcessStream::Virtual { items } => Pin::new(items).poll_next(cx),
         }
     }
 }
@@ -2,40 +2,126 @@
 // This file is subject to the terms and conditions defined in
 // file 'LICENSE', which is part of this source code package.
 
+use crate::process_wrapper::ExitSts;
+use crate::template::Template;
 use crate::time_wrapper::Duration;
 use crate::time_wrapper::Instant;
 use color_eyre::Result;
 
+/// Placeholders of the status line template:
+/// - `{state}`: `running` or `sleeping`;
+/// - `{runs}`: number of runs so far;
+/// - `{unchanged}`: number of consecutive unchanged runs;
+/// - `{exit}`: exit status of the last run;
+/// - `{bar}`: progress bar while running, based on the duration of
+///   the previous run, or the time left while sleeping;
+/// - `{spinner}`: a spinning character;
+/// - `{inflight}`: number of overlapping runs in flight;
+/// - `{tracked}`: sparkline and statistics of the tracked value.
+pub const STATUS_PLACEHOLDERS: &[&str] = &[
+    "state",
+    "runs",
+    "unchanged",
+    "exit",
+    "bar",
+    "spinner",
+    "inflight",
+    "tracked",
+];
+
 // Basic functions:
 
-fn get_prefix(unchanged_runs: u32) -> String {
-    if unchanged_runs == 0 {
-        "".to_string()
-    } else {
-        format!("unchanged {unchanged_runs}, ")
+/// Counters shown at the start of the status line.
+#[derive(Debug, Default, Clone)]
+pub struct Counters {
+    /// Current number of unchanged runs.
+    pub unchanged_runs: u32,
+    /// Number of unchanged runs after which we exit, if any.
+    pub until_stable: Option<u32>,
+    /// Number of runs in flight, only shown when runs can overlap.
+    pub inflight: Option<usize>,
+    /// Series of tracked values, shown as a sparkline.
+    pub tracked: Vec<f64>,
+    /// Total number of runs, only shown by status templates.
+    pub runs: u32,
+    /// Exit status of the last run, only shown by status templates.
+    pub exit: Option<ExitSts>,
+}
+
+fn min_max(values: &[f64]) -> (f64, f64) {
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    (min, max)
+}
+
+/// Renders the values as a sparkline of unicode block characters.
+fn sparkline(values: &[f64]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let (min, max) = min_max(values);
+    values
+        .iter()
+        .map(|value| {
+            if max > min {
+                let ratio = (value - min) / (max - min);
+                BLOCKS[(ratio * (BLOCKS.len() - 1) as f64).round() as usize]
+            } else {
+                BLOCKS[0]
+            }
+        })
+        .collect()
+}
+
+fn tracked(counters: &Counters) -> Option<String> {
+    let last = counters.tracked.last()?;
+    let (min, max) = min_max(&counters.tracked);
+    Some(format!(
+        "{} min {min} max {max} last {last}",
+        sparkline(&counters.tracked)
+    ))
+}
+
+fn get_prefix(counters: &Counters) -> String {
+    let mut prefix = String::new();
+    if let Some(tracked) = tracked(counters) {
+        prefix.push_str(&format!("{tracked}, "));
+    }
+    if let Some(inflight) = counters.inflight {
+        prefix.push_str(&format!("{inflight} in flight, "));
+    }
+    if let Some(until_stable) = counters.until_stable {
+        prefix.push_str(&format!(
+            "unchanged {}/{until_stable}, ",
+            counters.unchanged_runs
+        ));
+    } else if counters.unchanged_runs != 0 {
+        prefix.push_str(&format!("unchanged {}, ", counters.unchanged_runs));
     }
+    prefix
 }
 
 pub fn progbar_sleeping(
-    unchanged_runs: u32,
+    counters: &Counters,
     sleep: &Duration,
     now: &Instant,
     deadline: &Instant,
     spinner: char,
 ) -> String {
-    let prefix = get_prefix(unchanged_runs);
-    if sleep.num_seconds() > 1 {
-        let left = deadline - now;
-        let left = left.num_seconds() + 1;
-        format!("{prefix}sleeping for {left}s [{spinner}]")
-    } else {
-        format!("{prefix}sleeping [{spinner}]")
+    let prefix = get_prefix(counters);
+    match sleep_left(sleep, now, deadline) {
+        Some(left) => format!("{prefix}sleeping for {left} [{spinner}]"),
+        None => format!("{prefix}sleeping [{spinner}]"),
     }
 }
 
+/// Returns the time left to sleep, if the sleep is long enough to be
+/// worth showing.
+pub fn sleep_left(sleep: &Duration, now: &Instant, deadline: &Instant) -> Option<String> {
+    (sleep.num_seconds() > 1).then(|| format!("{}s", (deadline - now).num_seconds() + 1))
+}
+
 pub fn progbar_running(
     width: usize,
-    unchanged_runs: u32,
+    counters: &Counters,
     now: &Instant,
     start: &Instant,
     duration: Option<Duration>,
@@ -44,49 +130,94 @@ pub fn progbar_running(
 ) -> Result<String> {
     let duration = duration.unwrap_or_default();
     let duration_millis = duration.num_milliseconds();
-    let prefix = get_prefix(unchanged_runs);
+    let prefix = get_prefix(counters);
     if duration_millis == 0 || refresh.num_milliseconds() == 0 {
         return Ok(format!("{prefix}running [{spinner}]"));
     }
     let head = format!("{prefix}running [");
     let tail = format!("] [{spinner}]");
-    let barsize = {
-        let b = usize::try_from(duration_millis / refresh.num_milliseconds())?;
-        let overhead = head.len() + tail.len() + 1;
-        debug_assert!(
-            width >= overhead,
-            "width {width} not greater than overhead {overhead}",
-        );
-        if b + overhead > width {
-            width - overhead
-        } else {
-            b
-        }
-    };
-    Ok(if barsize <= 1 {
+    let overhead = head.chars().count() + tail.chars().count() + 1;
+    let bar = bar(
+        width.saturating_sub(overhead),
+        now,
+        start,
+        &duration,
+        refresh,
+    )?;
+    Ok(if bar.is_empty() {
         format!("{prefix}running [{spinner}]")
     } else {
-        let elapsed = now - start;
-        let ratio = elapsed.num_milliseconds() as f32 / duration_millis as f32;
-        let left = if ratio < 1_f32 {
-            ((barsize as f32) * ratio).ceil() as usize
-        } else {
-            barsize
-        };
-        let right = barsize.saturating_sub(left);
-        let marker = if elapsed > duration { "=" } else { ">" };
-        format!(
-            "{}{:=>left$}{:right$}{}",
-            head,
-            marker,
-            "",
-            tail,
-            left = left,
-            right = right
-        )
+        format!("{head}{bar}{tail}")
     })
 }
 
+/// Returns the progress bar of the run, with at most `width`
+/// characters; empty if we don't know how long the run takes.
+pub fn bar(
+    width: usize,
+    now: &Instant,
+    start: &Instant,
+    duration: &Duration,
+    refresh: &Duration,
+) -> Result<String> {
+    let duration_millis = duration.num_milliseconds();
+    if duration_millis == 0 || refresh.num_milliseconds() == 0 {
+        return Ok(String::new());
+    }
+    let barsize = usize::try_from(duration_millis / refresh.num_milliseconds())?.min(width);
+    if barsize <= 1 {
+        return Ok(String::new());
+    }
+    let elapsed = now - start;
+    let ratio = elapsed.num_milliseconds() as f32 / duration_millis as f32;
+    let left = if ratio < 1_f32 {
+        ((barsize as f32) * ratio).ceil() as usize
+    } else {
+        barsize
+    };
+    let right = barsize.saturating_sub(left);
+    let marker = if elapsed > *duration { "=" } else { ">" };
+    Ok(format!(
+        "{:=>left$}{:right$}",
+        marker,
+        "",
+        left = left,
+        right = right
+    ))
+}
+
+/// Renders the status line template with at most `width` characters;
+/// `bar` gets the width left by the rest of the template.
+pub fn status_template(
+    template: &Template,
+    width: usize,
+    counters: &Counters,
+    state: &str,
+    bar: impl Fn(usize) -> String,
+    spinner: char,
+) -> String {
+    let render = |bar: &str| {
+        template.render(|name| match name {
+            "state" => state.to_owned(),
+            "runs" => counters.runs.to_string(),
+            "unchanged" => counters.unchanged_runs.to_string(),
+            "exit" => counters
+                .exit
+                .as_ref()
+                .map(ExitSts::to_string)
+                .unwrap_or_default(),
+            "bar" => bar.to_owned(),
+            "spinner" => spinner.to_string(),
+            "inflight" => counters.inflight.map(|i| i.to_string()).unwrap_or_default(),
+            "tracked" => tracked(counters).unwrap_or_default(),
+            _ => String::new(),
+        })
+    };
+    // A non-empty bar keeps the spaces around it:
+    let overhead = render("=").chars().count() - 1;
+    render(&bar(width.saturating_sub(overhead)))
+}
+
 pub fn spinner_get(spinner: &mut char) -> char {
     *spinner = match spinner {
         '/' => '-',
@@ -109,7 +240,6 @@ mod test {
     #[test]
     fn spinner() {
         let spins = (1..6)
-            .into_iter()
             .scan('/', |spinner, _| Some(spinner_get(spinner)))
             .collect::<Vec<_>>();
         assert_eq!(spins, vec!['-', '\\', '|', '/', '-']);
@@ -127,7 +257,7 @@ mod test {
         let sleep = Duration::seconds(1);
         let now = Instant::default();
         let deadline = &now + &sleep;
-        let result = progbar_sleeping(0, &sleep, &now, &deadline, '-');
+        let result = progbar_sleeping(&Counters::default(), &sleep, &now, &deadline, '-');
         assert!(result.contains("sleeping"));
         assert!(result.contains("-"));
         assert!(!result.contains("unchanged"));
@@ -140,7 +270,7 @@ mod test {
         let now = &start + &Duration::seconds(2);
         let result = progbar_running(
             150,
-            0,
+            &Counters::default(),
             &now,
             &start,
             Some(duration),
@@ -153,4 +283,83 @@ mod test {
         assert!(text.contains("\\"));
         assert!(text.contains("["));
     }
+
+    #[test]
+    fn prefix_counters() {
+        assert_eq!(get_prefix(&Counters::default()), "");
+        let mut counters = Counters {
+            unchanged_runs: 3,
+            inflight: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(get_prefix(&counters), "2 in flight, unchanged 3, ");
+        let sleep = Duration::seconds(5);
+        let now = Instant::default();
+        let deadline = &now + &sleep;
+        let result = progbar_sleeping(&counters, &sleep, &now, &deadline, '-');
+        assert_eq!(result, "2 in flight, unchanged 3, sleeping for 6s [-]");
+        counters.until_stable = Some(5);
+        assert_eq!(get_prefix(&counters), "2 in flight, unchanged 3/5, ");
+        counters.unchanged_runs = 0;
+        assert_eq!(get_prefix(&counters), "2 in flight, unchanged 0/5, ");
+        counters.tracked = vec![2.0, 4.0, 3.0];
+        assert_eq!(
+            get_prefix(&counters),
+            "▁█▅ min 2 max 4 last 3, 2 in flight, unchanged 0/5, "
+        );
+    }
+
+    #[test]
+    fn status_templates() -> Result<()> {
+        let template = Template::new(
+            "{state} run {runs} unchanged {unchanged} last {exit} {bar}",
+            STATUS_PLACEHOLDERS,
+        )?;
+        let counters = Counters {
+            unchanged_runs: 2,
+            runs: 7,
+            exit: Some(ExitSts::Code(1)),
+            ..Default::default()
+        };
+        let status = |width| {
+            status_template(
+                &template,
+                width,
+                &counters,
+                "running",
+                |w| "=".repeat(w),
+                '-',
+            )
+        };
+        assert_eq!(status(45), "running run 7 unchanged 2 last code 1 =======");
+        assert_eq!(status(10), "running run 7 unchanged 2 last code 1");
+        let template = Template::new("{tracked} {state} [{spinner}]", STATUS_PLACEHOLDERS)?;
+        let status = status_template(&template, 80, &counters, "sleeping", |_| String::new(), '|');
+        assert_eq!(status, "sleeping [|]");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bar() -> Result<()> {
+        let start = Instant::default();
+        let now = &start + &Duration::seconds(1);
+        let refresh = Duration::milliseconds(250);
+        assert_eq!(
+            bar(80, &now, &start, &Duration::seconds(2), &refresh)?,
+            "===>    "
+        );
+        assert_eq!(
+            bar(4, &now, &start, &Duration::seconds(2), &refresh)?,
+            "=>  "
+        );
+        assert_eq!(bar(80, &now, &start, &Duration::default(), &refresh)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparkline() {
+        assert_eq!(sparkline(&[]), "");
+        assert_eq!(sparkline(&[1.0, 1.0]), "▁▁");
+        assert_eq!(sparkline(&[0.0, 7.0, 1.0, 3.5]), "▁█▂▅");
+    }
 }
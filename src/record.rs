@@ -0,0 +1,387 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Recording of sessions and their replay.
+//!
+//! A recording is the stream of [`EItem`]s yielded by the
+//! [`Engine`](crate::engine::Engine), stored as JSON lines after a
+//! header line with the command and how many of its runs could
+//! overlap. Replaying feeds the items back to the
+//! [`View`](crate::view::View) with the recorded timestamps, which
+//! makes the output deterministic.
+//!
+//! [`EData::Tick`] items are not recorded, as they only drive the
+//! animations; the replay generates them while waiting for the next
+//! item.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde_json::Value;
+use serde_json::json;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+use tokio_stream::Stream;
+
+use crate::engine::EData;
+use crate::engine::EItem;
+use crate::process_wrapper::Cmd;
+use crate::process_wrapper::ExitSts;
+use crate::sys::SysApi;
+use crate::time_wrapper::Duration;
+use crate::time_wrapper::Instant;
+
+/// Version of the recording format, in the header.
+const VERSION: u64 = 1;
+
+/// Format of the timestamps.
+const TIMESTAMP: &str = "%Y-%m-%dT%H:%M:%S%.6fZ";
+
+/// The error kinds we recover from recordings; the others become
+/// [`io::ErrorKind::Other`].
+const ERROR_KINDS: &[io::ErrorKind] = &[
+    io::ErrorKind::NotFound,
+    io::ErrorKind::PermissionDenied,
+    io::ErrorKind::BrokenPipe,
+    io::ErrorKind::Interrupted,
+    io::ErrorKind::InvalidInput,
+    io::ErrorKind::InvalidData,
+    io::ErrorKind::UnexpectedEof,
+    io::ErrorKind::Unsupported,
+    io::ErrorKind::OutOfMemory,
+];
+
+fn timestamp(instant: &Instant) -> Value {
+    Value::from(instant.format(TIMESTAMP, true))
+}
+
+fn exit_to_json(sts: &ExitSts) -> Value {
+    match sts {
+        ExitSts::Success => json!("success"),
+        ExitSts::Code(code) => json!({"code": code}),
+        ExitSts::Signal(signal) => json!({"signal": signal}),
+    }
+}
+
+fn exit_from_json(value: &Value) -> Option<ExitSts> {
+    if value.as_str() == Some("success") {
+        Some(ExitSts::Success)
+    } else if let Some(code) = value.get("code") {
+        Some(ExitSts::Code(u8::try_from(code.as_u64()?).ok()?))
+    } else {
+        let signal = value.get("signal")?.as_i64()?;
+        Some(ExitSts::Signal(i32::try_from(signal).ok()?))
+    }
+}
+
+fn to_json(item: &EItem) -> Value {
+    let mut value = match &item.data {
+        EData::StartRun => json!({"type": "start_run"}),
+        EData::StartSleep(deadline) => {
+            json!({"type": "start_sleep", "deadline": timestamp(deadline)})
+        }
+        EData::LineOut(line) => json!({"type": "line_out", "line": line}),
+        EData::LineErr(line) => json!({"type": "line_err", "line": line}),
+        EData::Msg(msg) => json!({"type": "msg", "msg": msg}),
+        EData::Done(sts) => json!({"type": "done", "exit": exit_to_json(sts)}),
+        EData::Err(kind) => json!({"type": "err", "kind": format!("{kind:?}")}),
+        EData::Tick => json!({"type": "tick"}),
+    };
+    value["time"] = timestamp(&item.time);
+    if let Some(run) = item.run {
+        value["run"] = json!(run);
+    }
+    value
+}
+
+fn from_json(value: &Value) -> Option<EItem> {
+    let text = |key: &str| value.get(key)?.as_str().map(String::from);
+    let data = match value.get("type")?.as_str()? {
+        "start_run" => EData::StartRun,
+        "start_sleep" => EData::StartSleep(Instant::from_str(&text("deadline")?).ok()?),
+        "line_out" => EData::LineOut(text("line")?),
+        "line_err" => EData::LineErr(text("line")?),
+        "msg" => EData::Msg(text("msg")?),
+        "done" => EData::Done(exit_from_json(value.get("exit")?)?),
+        "err" => {
+            let kind = text("kind")?;
+            let found = ERROR_KINDS.iter().find(|k| format!("{k:?}") == kind);
+            EData::Err(found.copied().unwrap_or(io::ErrorKind::Other))
+        }
+        "tick" => EData::Tick,
+        _ => return None,
+    };
+    let run = match value.get("run") {
+        Some(run) => Some(u32::try_from(run.as_u64()?).ok()?),
+        None => None,
+    };
+    Some(EItem {
+        time: Instant::from_str(&text("time")?).ok()?,
+        run,
+        data,
+    })
+}
+
+/// A recording loaded from a file.
+#[derive(Debug)]
+pub struct Recording {
+    /// The command that was run.
+    pub cmd: Cmd,
+    /// The `--max-concurrent` of the session.
+    pub max_concurrent: u32,
+    /// The items yielded by the engine.
+    pub items: VecDeque<EItem>,
+}
+
+impl Recording {
+    /// Loads the recording from the file at `path`.
+    pub fn load<SI: SysApi>(sys: &SI, path: &Path) -> Result<Self> {
+        parse(&sys.read_file(path)?)
+    }
+}
+
+/// Parses a recording.
+fn parse(contents: &str) -> Result<Recording> {
+    let mut lines = contents.lines().enumerate();
+    let header = (lines.next())
+        .and_then(|(_, line)| serde_json::from_str::<Value>(line).ok())
+        .filter(|header| header.get("ogle").and_then(Value::as_u64) == Some(VERSION))
+        .ok_or_else(|| eyre!("not an ogle recording, or from an unsupported version"))?;
+    let command = (header.get("command").and_then(Value::as_array))
+        .and_then(|args| {
+            args.iter()
+                .map(|arg| arg.as_str().map(String::from))
+                .collect::<Option<Vec<_>>>()
+        })
+        .ok_or_else(|| eyre!("invalid command in the recording header"))?;
+    let max_concurrent = (header.get("max_concurrent").and_then(Value::as_u64))
+        .and_then(|n| u32::try_from(n).ok())
+        .filter(|n| *n > 0)
+        .ok_or_else(|| eyre!("invalid max_concurrent in the recording header"))?;
+    let items = lines
+        .map(|(i, line)| {
+            (serde_json::from_str::<Value>(line).ok())
+                .and_then(|value| from_json(&value))
+                .ok_or_else(|| eyre!("invalid item in line {} of the recording", i + 1))
+        })
+        .collect::<Result<_>>()?;
+    Ok(Recording {
+        cmd: Cmd::from(command),
+        max_concurrent,
+        items,
+    })
+}
+
+// Recorder //////////////////////////////////////////////////////////
+
+/// Appends the items to a recording file.
+#[derive(Debug)]
+pub struct Recorder {
+    path: PathBuf,
+}
+
+impl Recorder {
+    /// Creates the recording file, writing the header.
+    pub fn new<SI: SysApi>(
+        sys: &mut SI,
+        path: PathBuf,
+        cmd: &Cmd,
+        max_concurrent: usize,
+    ) -> io::Result<Self> {
+        let header = json!({
+            "ogle": VERSION,
+            "command": cmd.args(),
+            "max_concurrent": max_concurrent,
+        });
+        sys.write_file(&path, &format!("{header}\n"))?;
+        Ok(Self { path })
+    }
+
+    /// Appends the item to the recording, unless it's a tick.
+    pub fn record<SI: SysApi>(&self, sys: &mut SI, item: &EItem) -> io::Result<()> {
+        if item.data == EData::Tick {
+            return Ok(());
+        }
+        sys.append_file(&self.path, &format!("{}\n", to_json(item)))
+    }
+}
+
+// Replay ////////////////////////////////////////////////////////////
+
+/// A stream that yields recorded items, keeping their relative timing
+/// scaled by the speed; with a speed of 0, the items are yielded
+/// without waiting.
+///
+/// While waiting for the next item, it yields [`EData::Tick`] items
+/// at the refresh interval, if one was provided.
+#[derive(Debug)]
+pub struct Replay {
+    items: VecDeque<EItem>,
+    speed: f64,
+    refresh: Option<Duration>,
+    /// When the replay started, and the time of the first item.
+    origin: Option<(tokio::time::Instant, Instant)>,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+    ticker: Option<tokio::time::Interval>,
+    /// Set to the time of each item we yield.
+    clock: Option<Rc<Cell<Instant>>>,
+}
+
+impl Replay {
+    pub fn new(items: VecDeque<EItem>, speed: f64) -> Self {
+        Self {
+            items,
+            speed,
+            refresh: None,
+            origin: None,
+            sleep: None,
+            ticker: None,
+            clock: None,
+        }
+    }
+
+    /// Sets the interval of the ticks we yield between the items.
+    pub fn with_refresh(mut self, refresh: Duration) -> Self {
+        self.refresh = Some(refresh);
+        self
+    }
+
+    /// Sets the clock that follows the time of the yielded items.
+    pub fn with_clock(mut self, clock: Rc<Cell<Instant>>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+}
+
+impl Stream for Replay {
+    type Item = EItem;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some(item) = this.items.front() else {
+            return Poll::Ready(None);
+        };
+        if this.speed > 0.0 {
+            let (start, first) = *this
+                .origin
+                .get_or_insert_with(|| (tokio::time::Instant::now(), item.time));
+            let offset = std::time::Duration::from(&item.time - &first);
+            let deadline = start + offset.div_f64(this.speed);
+            let sleep = this
+                .sleep
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep_until(deadline)));
+            if sleep.as_mut().poll(cx).is_pending() {
+                if let Some(refresh) = this.refresh {
+                    let ticker = this.ticker.get_or_insert_with(|| {
+                        let period = std::time::Duration::from(refresh);
+                        tokio::time::interval_at(start + period, period)
+                    });
+                    if ticker.poll_tick(cx).is_ready() {
+                        let elapsed = start.elapsed().mul_f64(this.speed);
+                        let time = &first + &Duration::from(elapsed);
+                        if let Some(clock) = &this.clock {
+                            clock.set(time);
+                        }
+                        return Poll::Ready(Some(EItem::new(time, EData::Tick)));
+                    }
+                }
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+        if let Some(clock) = &this.clock {
+            clock.set(item.time);
+        }
+        Poll::Ready(this.items.pop_front())
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sys::SysVirtual;
+    use std::path::Path;
+    use tokio_stream::StreamExt;
+
+    fn items() -> VecDeque<EItem> {
+        let mut now = Instant::default();
+        VecDeque::from([
+            EItem::new(now.incr(), EData::StartRun).with_run(1),
+            EItem::new(now.incr(), EData::LineOut("out".to_owned())).with_run(1),
+            EItem::new(now.incr(), EData::LineErr("err".to_owned())).with_run(1),
+            EItem::new(now.incr(), EData::Done(ExitSts::Code(2))).with_run(1),
+            EItem::new(now.incr(), EData::Done(ExitSts::Signal(9))).with_run(2),
+            EItem::new(now.incr(), EData::StartSleep(&now + &Duration::seconds(3))),
+            EItem::new(now.incr(), EData::Tick),
+            EItem::msg(now.incr(), "user exit".to_owned()),
+            EItem::new(now.incr(), io::ErrorKind::NotFound),
+            EItem::new(now.incr(), io::ErrorKind::AddrInUse),
+        ])
+    }
+
+    #[test]
+    fn test_record_load() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let path = Path::new("session.ogle");
+        let cmd = Cmd::from(&["echo", "a b"][..]);
+        let recorder = Recorder::new(&mut sys, path.to_owned(), &cmd, 3)?;
+        for item in items() {
+            recorder.record(&mut sys, &item)?;
+        }
+        let loaded = Recording::load(&sys, path)?;
+        assert_eq!(loaded.cmd.args(), cmd.args());
+        assert_eq!(loaded.max_concurrent, 3);
+        let mut expected = items();
+        // Unknown error kinds are not recovered:
+        expected[9].data = EData::Err(io::ErrorKind::Other);
+        // Ticks are not recorded:
+        expected.remove(6);
+        assert_eq!(loaded.items, expected);
+        assert!(Recording::load(&sys, Path::new("missing.ogle")).is_err());
+        assert!(parse("").is_err());
+        assert!(parse("{\"ogle\": 1, \"command\": [], \"max_concurrent\": 1}\n{}").is_err());
+        assert!(parse("{\"ogle\": 1, \"command\": [], \"max_concurrent\": 0}").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let clock = Rc::new(Cell::new(Instant::default()));
+        let replay = Replay::new(items(), 0.0).with_clock(clock.clone());
+        assert_eq!(replay.collect::<Vec<_>>().await, Vec::from(items()));
+        assert_eq!(clock.get(), items()[9].time);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_speed() {
+        let start = tokio::time::Instant::now();
+        let replay = Replay::new(items(), 4.0);
+        assert_eq!(replay.collect::<Vec<_>>().await, Vec::from(items()));
+        // The items span 9s, replayed 4 times faster:
+        assert_eq!(start.elapsed(), std::time::Duration::from_millis(2250));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_ticks() {
+        let items = items().into_iter().take(2).collect::<VecDeque<_>>();
+        let first = items[0].time;
+        let replay = Replay::new(items.clone(), 2.0).with_refresh(Duration::milliseconds(200));
+        let tick = |millis| EItem::new(&first + &Duration::milliseconds(millis), EData::Tick);
+        // The items are 1s apart, replayed 2 times faster, with ticks
+        // every 200ms of real time:
+        assert_eq!(
+            replay.collect::<Vec<_>>().await,
+            vec![items[0].clone(), tick(400), tick(800), items[1].clone()]
+        );
+    }
+}
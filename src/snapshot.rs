@@ -0,0 +1,103 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Snapshot of the comparison state, persisted in the `--state-file`
+//! so that a restarted ogle resumes change detection.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use serde_json::Value;
+use serde_json::json;
+use std::io;
+use std::path::Path;
+
+use crate::sys::SysApi;
+
+/// The lines that the next run is compared against, and the counters.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The command, as we don't resume from the runs of another one.
+    pub command: String,
+    pub total_runs: u32,
+    pub unchanged_runs: u32,
+    pub stdout: Vec<String>,
+    pub stderr: Vec<String>,
+}
+
+fn strings(value: &Value) -> Option<Vec<String>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|v| v.as_str().map(String::from))
+        .collect()
+}
+
+fn counter(value: &Value) -> Option<u32> {
+    u32::try_from(value.as_u64()?).ok()
+}
+
+impl Snapshot {
+    /// Loads the snapshot from the file at `path`, if it exists.
+    pub fn load<SI: SysApi>(sys: &SI, path: &Path) -> Result<Option<Self>> {
+        let contents = match sys.read_file(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let value = serde_json::from_str::<Value>(&contents)?;
+        let snapshot = (|| {
+            Some(Snapshot {
+                command: value.get("command")?.as_str()?.to_owned(),
+                total_runs: counter(value.get("total_runs")?)?,
+                unchanged_runs: counter(value.get("unchanged_runs")?)?,
+                stdout: strings(value.get("stdout")?)?,
+                stderr: strings(value.get("stderr")?)?,
+            })
+        })();
+        snapshot
+            .map(Some)
+            .ok_or_else(|| eyre!("invalid state file {}", path.display()))
+    }
+
+    /// Saves the snapshot to the file at `path`.
+    pub fn save<SI: SysApi>(&self, sys: &mut SI, path: &Path) -> io::Result<()> {
+        let value = json!({
+            "command": self.command,
+            "total_runs": self.total_runs,
+            "unchanged_runs": self.unchanged_runs,
+            "stdout": self.stdout,
+            "stderr": self.stderr,
+        });
+        sys.write_file(path, &format!("{value}\n"))
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::sys::SysVirtual;
+
+    #[test]
+    fn test_save_load() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let path = Path::new("state.json");
+        assert_eq!(Snapshot::load(&sys, path)?, None);
+        let snapshot = Snapshot {
+            command: "date".to_owned(),
+            total_runs: 5,
+            unchanged_runs: 2,
+            stdout: vec!["a".to_owned(), "b".to_owned()],
+            stderr: vec![],
+        };
+        snapshot.save(&mut sys, path)?;
+        assert_eq!(Snapshot::load(&sys, path)?, Some(snapshot));
+        sys.write_file(path, "{\"command\": \"date\"}")?;
+        assert!(Snapshot::load(&sys, path).is_err());
+        sys.write_file(path, "{")?;
+        assert!(Snapshot::load(&sys, path).is_err());
+        Ok(())
+    }
+}
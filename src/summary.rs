@@ -0,0 +1,209 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Summary of the session, printed when ogle exits.
+
+use serde_json::json;
+use std::collections::HashSet;
+use std::hash::DefaultHasher;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+use crate::process_wrapper::ExitSts;
+use crate::time_wrapper::Duration;
+use crate::time_wrapper::Instant;
+
+/// Statistics of the runs.
+#[derive(Debug, Default)]
+pub struct Summary {
+    /// Hashes of the distinct outputs.
+    outputs: HashSet<u64>,
+    /// Number of runs by exit status, in order of first appearance.
+    statuses: Vec<(ExitSts, u32)>,
+    durations: Vec<Duration>,
+    /// Start of the first run and end of the last one.
+    span: Option<(Instant, Instant)>,
+}
+
+/// Minimum, average, 95th percentile and maximum of the durations.
+#[derive(Debug, PartialEq, Eq)]
+struct Stats {
+    min: Duration,
+    avg: Duration,
+    p95: Duration,
+    max: Duration,
+}
+
+fn seconds(duration: &Duration) -> f64 {
+    duration.num_milliseconds() as f64 / 1000.0
+}
+
+impl Summary {
+    /// Adds a run, with the lines of its output.
+    pub fn push(&mut self, start: Instant, end: Instant, sts: &ExitSts, output: &[String]) {
+        let mut hasher = DefaultHasher::new();
+        output.hash(&mut hasher);
+        self.outputs.insert(hasher.finish());
+        match self.statuses.iter_mut().find(|(s, _)| s == sts) {
+            Some((_, count)) => *count += 1,
+            None => self.statuses.push((sts.clone(), 1)),
+        }
+        self.durations.push(&end - &start);
+        self.span = Some(match self.span {
+            Some((first, _)) => (first, end),
+            None => (start, end),
+        });
+    }
+
+    fn runs(&self) -> usize {
+        self.durations.len()
+    }
+
+    fn stats(&self) -> Option<Stats> {
+        let mut durations = self.durations.clone();
+        durations.sort();
+        let total = durations
+            .iter()
+            .map(Duration::num_milliseconds)
+            .sum::<i64>();
+        // Nearest-rank percentile:
+        let ip95 = (durations.len() * 95).div_ceil(100).saturating_sub(1);
+        Some(Stats {
+            min: *durations.first()?,
+            avg: Duration::milliseconds(total / i64::try_from(durations.len()).ok()?),
+            p95: durations[ip95],
+            max: *durations.last()?,
+        })
+    }
+
+    fn span(&self) -> Duration {
+        self.span
+            .map(|(first, last)| &last - &first)
+            .unwrap_or_default()
+    }
+
+    /// Returns the summary as human-readable lines.
+    pub fn text(&self) -> Vec<String> {
+        let mut lines = vec![format!(
+            "summary: {} runs, {} distinct outputs, over {}",
+            self.runs(),
+            self.outputs.len(),
+            self.span()
+        )];
+        if !self.statuses.is_empty() {
+            let statuses = (self.statuses.iter())
+                .map(|(sts, count)| format!("{sts} {count}"))
+                .collect::<Vec<_>>();
+            lines.push(format!("exit statuses: {}", statuses.join(", ")));
+        }
+        if let Some(stats) = self.stats() {
+            lines.push(format!(
+                "durations: min {} avg {} p95 {} max {}",
+                stats.min, stats.avg, stats.p95, stats.max
+            ));
+        }
+        lines
+    }
+
+    /// Returns the summary as a single-line JSON object.
+    pub fn json(&self) -> String {
+        let statuses = (self.statuses.iter())
+            .map(|(sts, count)| (sts.to_string(), json!(count)))
+            .collect::<serde_json::Map<_, _>>();
+        let durations = self.stats().map(|stats| {
+            json!({
+                "min": seconds(&stats.min),
+                "avg": seconds(&stats.avg),
+                "p95": seconds(&stats.p95),
+                "max": seconds(&stats.max),
+            })
+        });
+        let time = |instant: &Instant| instant.format("%Y-%m-%dT%H:%M:%S%.3fZ", true);
+        json!({
+            "runs": self.runs(),
+            "distinct_outputs": self.outputs.len(),
+            "exit_statuses": statuses,
+            "durations": durations,
+            "start": self.span.map(|(first, _)| time(&first)),
+            "end": self.span.map(|(_, last)| time(&last)),
+            "span": seconds(&self.span()),
+        })
+        .to_string()
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn summary() -> Summary {
+        let mut summary = Summary::default();
+        let mut now = Instant::default();
+        let output = |s: &str| vec![s.to_owned()];
+        for (sts, out) in [
+            (ExitSts::Success, "a"),
+            (ExitSts::Code(1), "b"),
+            (ExitSts::Success, "a"),
+        ] {
+            let start = now.incr();
+            summary.push(start, now.incr(), &sts, &output(out));
+        }
+        summary
+    }
+
+    #[test]
+    fn test_stats() {
+        assert_eq!(Summary::default().stats(), None);
+        let mut summary = Summary::default();
+        let start = Instant::default();
+        for millis in [300, 100, 200, 1000] {
+            let end = &start + &Duration::milliseconds(millis);
+            summary.push(start, end, &ExitSts::Success, &[]);
+        }
+        assert_eq!(
+            summary.stats(),
+            Some(Stats {
+                min: Duration::milliseconds(100),
+                avg: Duration::milliseconds(400),
+                p95: Duration::milliseconds(1000),
+                max: Duration::milliseconds(1000),
+            })
+        );
+    }
+
+    #[test]
+    fn test_text() {
+        assert_eq!(
+            summary().text(),
+            vec![
+                "summary: 3 runs, 2 distinct outputs, over +5.000s",
+                "exit statuses: success 2, code 1 1",
+                "durations: min +1.000s avg +1.000s p95 +1.000s max +1.000s",
+            ]
+        );
+        assert_eq!(
+            Summary::default().text(),
+            vec!["summary: 0 runs, 0 distinct outputs, over +0.000s"]
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let value = serde_json::from_str::<serde_json::Value>(&summary().json()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "runs": 3,
+                "distinct_outputs": 2,
+                "exit_statuses": {"success": 2, "code 1": 1},
+                "durations": {"min": 1.0, "avg": 1.0, "p95": 1.0, "max": 1.0},
+                "start": "1970-01-01T00:00:00.000Z",
+                "end": "1970-01-01T00:00:05.000Z",
+                "span": 5.0,
+            })
+        );
+    }
+}
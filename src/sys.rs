@@ -11,32 +11,49 @@
 //! essentially a mock object.
 
 use color_eyre::Result;
+use std::cell::Cell;
 use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::collections::VecDeque;
+use std::path::Path;
+use std::path::PathBuf;
+use std::rc::Rc;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tokio_process_stream as tps;
 
+use crate::engine::EItem;
 use crate::process_wrapper::Cmd;
+use crate::process_wrapper::ExitSts;
 use crate::process_wrapper::Item;
 use crate::process_wrapper::ProcessStream;
+use crate::record::Replay;
 use crate::term_wrapper;
 use crate::time_wrapper::Duration;
 use crate::time_wrapper::Instant;
+use crate::time_wrapper::Timeline;
+use crate::user_wrapper::UserEvent;
 use crate::user_wrapper::UserStream;
 
 // SysApi ////////////////////////////////////////////////////////////
 
+mod sealed {
+    /// Keeps the implementations of [`SysApi`](super::SysApi) in this
+    /// crate, as its methods use types that are not public.
+    pub trait Sealed {}
+}
+
 /// Trait providing the system functions available for mocking.
 ///
 /// Users should depend on this type and then take [`SysVirtual`] in
-/// tests or [`SysReal`] in production.
-pub trait SysApi: std::fmt::Debug + Clone + Default {
+/// tests or [`SysReal`] in production. It's sealed: the library users
+/// can only use the implementations in this module.
+pub trait SysApi: sealed::Sealed + std::fmt::Debug + Clone + Default {
     /// Returns an [`Instant`] that corresponds to the current
     /// wall-clock time.
     fn now(&self) -> Instant;
 
     /// Returns the width of the terminal.
-    #[allow(dead_code)]
     fn get_width(&self) -> Option<u16>;
 
     /// Starts the execution of the provided [`Cmd`] and returns the
@@ -48,7 +65,41 @@ pub trait SysApi: std::fmt::Debug + Clone + Default {
     /// the process finishes.
     fn run_command(&mut self, command: Cmd) -> Result<ProcessStream, std::io::Error>;
 
+    /// Starts the execution of the provided filter [`Cmd`], writing the
+    /// `input` lines to its `stdin`.
+    ///
+    /// The returned [`ProcessStream`] yields the lines printed by the
+    /// filter.
+    fn run_filter(
+        &mut self,
+        command: Cmd,
+        input: Vec<String>,
+    ) -> Result<ProcessStream, std::io::Error>;
+
+    /// Starts the execution of the provided [`Cmd`] in the background,
+    /// ignoring its output and exit status.
+    fn run_hook(&mut self, command: Cmd) -> Result<(), std::io::Error>;
+
+    /// Reads the whole contents of the file at `path`.
+    fn read_file(&self, path: &Path) -> Result<String, std::io::Error>;
+
+    /// Writes the file at `path`, creating its directory if needed and
+    /// replacing its previous contents.
+    fn write_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error>;
+
+    /// Removes the file at `path`.
+    fn remove_file(&mut self, path: &Path) -> Result<(), std::io::Error>;
+
+    /// Appends `contents` to the file at `path`.
+    fn append_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error>;
+
+    /// Returns the stream of keys pressed by the user, if we can read
+    /// them.
     fn user_stream(&mut self) -> Option<UserStream>;
+
+    /// Returns the stream of recorded items that the engine yields
+    /// instead of running the command, when replaying a session.
+    fn replay_stream(&mut self) -> Option<Replay>;
 }
 
 // SysReal ///////////////////////////////////////////////////////////
@@ -57,6 +108,8 @@ pub trait SysApi: std::fmt::Debug + Clone + Default {
 #[derive(Debug, Clone, Default)]
 pub struct SysReal {}
 
+impl sealed::Sealed for SysReal {}
+
 impl SysApi for SysReal {
     fn now(&self) -> Instant {
         Instant::from(chrono::offset::Utc::now())
@@ -68,9 +121,57 @@ impl SysApi for SysReal {
         let process_stream = tps::ProcessLineStream::try_from(Command::from(&cmd))?;
         Ok(ProcessStream::from(process_stream))
     }
+    fn run_filter(
+        &mut self,
+        cmd: Cmd,
+        input: Vec<String>,
+    ) -> Result<ProcessStream, std::io::Error> {
+        let mut command = Command::from(&cmd);
+        command.stdin(std::process::Stdio::piped());
+        let mut child = command.spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // Write in the background, as the filter may not read
+            // everything before writing
+            tokio::spawn(async move {
+                for line in input {
+                    let line = line + "\n";
+                    if stdin.write_all(line.as_bytes()).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Ok(ProcessStream::from(tps::ProcessLineStream::from(child)))
+    }
+    fn run_hook(&mut self, cmd: Cmd) -> Result<(), std::io::Error> {
+        let mut command = Command::from(&cmd);
+        command.stdout(std::process::Stdio::null());
+        command.stderr(std::process::Stdio::null());
+        // tokio reaps the child in the background when we drop it
+        command.spawn().map(|_| ())
+    }
+    fn read_file(&self, path: &Path) -> Result<String, std::io::Error> {
+        std::fs::read_to_string(path)
+    }
+    fn write_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, contents)
+    }
+    fn remove_file(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        std::fs::remove_file(path)
+    }
+    fn append_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error> {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path)?;
+        std::io::Write::write_all(&mut file, contents.as_bytes())
+    }
     fn user_stream(&mut self) -> Option<UserStream> {
         UserStream::new_real()
     }
+    fn replay_stream(&mut self) -> Option<Replay> {
+        None
+    }
 }
 
 // SysVirtual ////////////////////////////////////////////////////////
@@ -81,49 +182,293 @@ impl SysApi for SysReal {
 /// Namely:
 /// - [`SysVirtual::now`] starts at the
 ///   [epoch](chrono::DateTime::UNIX_EPOCH) and increments its return
-///   value by 1 second at every call.
-/// - [`SysVirtual::get_width`] always returns 80.
+///   value by 1 second at every call, unless we use the virtual clock
+///   of [`SysVirtual::with_virtual_clock`].
+/// - [`SysVirtual::get_width`] returns the widths provided to
+///   [`SysVirtual::set_widths`] in turn, repeating the last one, or
+///   80 if none were provided.
 /// - [`SysVirtual::run_command`] ignores the `cmd` argument and
-///   yields items from a list that was provided to
-///   [`SysVirtual::set_items`].
+///   yields the items of the next [`Script`] provided to
+///   [`SysVirtual::set_runs`], or nothing if there are no scripts
+///   left.
+/// - [`SysVirtual::user_stream`] yields the events provided to
+///   [`SysVirtual::set_user_events`].
+/// - [`SysVirtual::run_filter`] yields its input, like `cat`.
+/// - [`SysVirtual::run_hook`] does nothing.
+/// - [`SysVirtual::read_file`], [`SysVirtual::write_file`],
+///   [`SysVirtual::remove_file`] and [`SysVirtual::append_file`] work
+///   on an in-memory filesystem.
 #[derive(Debug, Clone, Default)]
 pub struct SysVirtual {
     now: RefCell<Instant>,
-    items: VecDeque<Item>,
+    /// The tokio time that corresponds to the epoch, with the virtual
+    /// clock.
+    clock: Option<tokio::time::Instant>,
+    widths: RefCell<VecDeque<Option<u16>>>,
+    runs: VecDeque<Script>,
+    user_events: VecDeque<(Duration, UserEvent)>,
+    files: BTreeMap<PathBuf, String>,
+}
+
+/// A scripted run of the command, for [`SysVirtual`].
+///
+/// The items are yielded in order, each after the delay set by the
+/// [`Script::wait`] that precedes it, in tokio time.
+#[derive(Debug, Clone, Default)]
+pub struct Script {
+    items: VecDeque<(Duration, Item)>,
+    delay: Duration,
 }
 
+#[allow(dead_code)]
+impl Script {
+    /// Waits for `delay` before yielding the next item.
+    pub fn wait(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    pub fn item(mut self, item: Item) -> Self {
+        let delay = std::mem::take(&mut self.delay);
+        self.items.push_back((delay, item));
+        self
+    }
+
+    /// Writes a line to stdout.
+    pub fn out(self, line: &str) -> Self {
+        self.item(Item::Stdout(line.to_owned()))
+    }
+
+    /// Writes a line to stderr.
+    pub fn err(self, line: &str) -> Self {
+        self.item(Item::Stderr(line.to_owned()))
+    }
+
+    /// Exits with `sts`.
+    pub fn exit(self, sts: ExitSts) -> Self {
+        self.item(Item::Done(Ok(sts)))
+    }
+}
+
+impl From<Vec<Item>> for Script {
+    /// Yields the items without waiting.
+    fn from(items: Vec<Item>) -> Self {
+        items.into_iter().fold(Script::default(), Script::item)
+    }
+}
+
+impl sealed::Sealed for SysVirtual {}
+
 impl SysApi for SysVirtual {
     /// Returns a "fake" current time by starting at the
     /// [epoch](chrono::DateTime::UNIX_EPOCH) and incrementing the
-    /// return value by 1 second at every call.
+    /// return value by 1 second at every call, or the time of the
+    /// virtual clock.
     fn now(&self) -> Instant {
+        if let Some(clock) = self.clock {
+            return &Instant::default() + &Duration::from(clock.elapsed());
+        }
         let mut now_ref = self.now.borrow_mut();
         let now = *now_ref;
         *now_ref = &now + &Duration::seconds(1);
         now
     }
+    /// Returns the widths provided to [`SysVirtual::set_widths`] in
+    /// turn, repeating the last one, or 80 if none were provided.
     fn get_width(&self) -> Option<u16> {
-        Some(80)
+        let mut widths = self.widths.borrow_mut();
+        if widths.len() > 1 {
+            widths.pop_front().flatten()
+        } else {
+            widths.front().copied().unwrap_or(Some(80))
+        }
     }
-    /// Yields items from the list that was provided to
-    /// [`SysVirtual::set_items`].
+    /// Yields the items of the next script provided to
+    /// [`SysVirtual::set_runs`].
     ///
     /// The `cmd` argument is not used.
     fn run_command(&mut self, _cmd: Cmd) -> Result<ProcessStream, std::io::Error> {
-        let items = std::mem::take(&mut self.items);
-        Ok(ProcessStream::from(items))
+        let script = self.runs.pop_front().unwrap_or_default();
+        Ok(ProcessStream::from(Timeline::from(script.items)))
+    }
+    /// Works as the identity, yielding the `input` lines, as if the
+    /// filter was `cat`.
+    ///
+    /// The `cmd` argument is not used.
+    fn run_filter(
+        &mut self,
+        _cmd: Cmd,
+        input: Vec<String>,
+    ) -> Result<ProcessStream, std::io::Error> {
+        let items = input
+            .into_iter()
+            .map(Item::Stdout)
+            .chain([Item::Done(Ok(ExitSts::Success))]);
+        Ok(ProcessStream::from(items.collect::<VecDeque<_>>()))
+    }
+    /// Does nothing.
+    fn run_hook(&mut self, _cmd: Cmd) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+    fn read_file(&self, path: &Path) -> Result<String, std::io::Error> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+    fn write_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error> {
+        self.files.insert(path.to_owned(), contents.to_owned());
+        Ok(())
+    }
+    fn remove_file(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        self.files
+            .remove(path)
+            .map(|_| ())
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+    fn append_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error> {
+        let file = self
+            .files
+            .get_mut(path)
+            .ok_or(std::io::ErrorKind::NotFound)?;
+        file.push_str(contents);
+        Ok(())
     }
     fn user_stream(&mut self) -> Option<UserStream> {
-        Some(UserStream::new_virtual())
+        let events = std::mem::take(&mut self.user_events);
+        Some(UserStream::new_virtual(Timeline::from(events)))
+    }
+    fn replay_stream(&mut self) -> Option<Replay> {
+        None
     }
 }
 
 impl SysVirtual {
+    /// Makes [`SysVirtual::now`] follow tokio's time, starting at the
+    /// epoch.
+    ///
+    /// When the test pauses tokio's time, with
+    /// `#[tokio::test(start_paused = true)]`, the clock only moves when
+    /// the test advances it or when the runtime is idle waiting for a
+    /// timer, like the scripted delays and the tickers of the engine;
+    /// the time we see is then independent of how many times we call
+    /// `now`.
+    #[allow(dead_code)]
+    pub fn with_virtual_clock(mut self) -> Self {
+        self.clock = Some(tokio::time::Instant::now());
+        self
+    }
+
     /// Sets the list that is going to be yielded by the stream
-    /// returned by [`SysVirtual::run_command`].
+    /// returned by the first [`SysVirtual::run_command`].
     #[allow(dead_code)]
     pub fn set_items(&mut self, items: Vec<Item>) {
-        self.items = items.into_iter().collect();
+        self.set_runs(vec![Script::from(items)]);
+    }
+
+    /// Sets the scripts of the runs, one for each
+    /// [`SysVirtual::run_command`].
+    #[allow(dead_code)]
+    pub fn set_runs(&mut self, runs: Vec<Script>) {
+        self.runs = runs.into();
+    }
+
+    /// Sets the events yielded by the user stream, each after its
+    /// delay, in tokio time.
+    #[allow(dead_code)]
+    pub fn set_user_events(&mut self, events: Vec<(Duration, UserEvent)>) {
+        self.user_events = events.into();
+    }
+
+    /// Sets the widths returned by [`SysVirtual::get_width`].
+    #[allow(dead_code)]
+    pub fn set_widths(&mut self, widths: Vec<Option<u16>>) {
+        self.widths = RefCell::new(widths.into());
+    }
+
+    /// Returns the paths of the files in the in-memory filesystem.
+    #[allow(dead_code)]
+    pub fn files(&self) -> Vec<&Path> {
+        self.files.keys().map(PathBuf::as_path).collect()
+    }
+}
+
+// SysReplay /////////////////////////////////////////////////////////
+
+/// `SysReplay` implements [`SysApi`] for replaying recorded sessions.
+///
+/// Namely:
+/// - [`SysReplay::replay_stream`] yields the recorded items, which the
+///   engine then yields instead of running the command.
+/// - [`SysReplay::run_command`] and [`SysReplay::run_filter`] fail,
+///   and [`SysReplay::run_hook`] does nothing, as we don't run
+///   anything while replaying.
+/// - [`SysReplay::now`] returns the time of the last replayed item,
+///   starting with the time of the first one.
+/// - The other functions are the ones from [`SysReal`].
+#[derive(Debug, Clone, Default)]
+pub struct SysReplay {
+    real: SysReal,
+    /// Shared with the [`Replay`], which advances it.
+    clock: Rc<Cell<Instant>>,
+    items: VecDeque<EItem>,
+    /// Speed factor of the replay; 0 replays without waiting.
+    speed: f64,
+}
+
+impl SysReplay {
+    pub fn new(items: VecDeque<EItem>, speed: f64) -> Self {
+        let real = SysReal::default();
+        let origin = items.front().map_or_else(|| real.now(), |item| item.time);
+        Self {
+            real,
+            clock: Rc::new(Cell::new(origin)),
+            items,
+            speed,
+        }
+    }
+}
+
+impl sealed::Sealed for SysReplay {}
+
+impl SysApi for SysReplay {
+    fn now(&self) -> Instant {
+        self.clock.get()
+    }
+    fn get_width(&self) -> Option<u16> {
+        self.real.get_width()
+    }
+    fn run_command(&mut self, _cmd: Cmd) -> Result<ProcessStream, std::io::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+    fn run_filter(
+        &mut self,
+        _cmd: Cmd,
+        _input: Vec<String>,
+    ) -> Result<ProcessStream, std::io::Error> {
+        Err(std::io::ErrorKind::Unsupported.into())
+    }
+    fn run_hook(&mut self, _cmd: Cmd) -> Result<(), std::io::Error> {
+        Ok(())
+    }
+    fn read_file(&self, path: &Path) -> Result<String, std::io::Error> {
+        self.real.read_file(path)
+    }
+    fn write_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error> {
+        self.real.write_file(path, contents)
+    }
+    fn remove_file(&mut self, path: &Path) -> Result<(), std::io::Error> {
+        self.real.remove_file(path)
+    }
+    fn append_file(&mut self, path: &Path, contents: &str) -> Result<(), std::io::Error> {
+        self.real.append_file(path, contents)
+    }
+    fn user_stream(&mut self) -> Option<UserStream> {
+        self.real.user_stream()
+    }
+    fn replay_stream(&mut self) -> Option<Replay> {
+        let items = std::mem::take(&mut self.items);
+        Some(Replay::new(items, self.speed).with_clock(self.clock.clone()))
     }
 }
 
@@ -164,6 +509,78 @@ pub mod test {
         Ok(())
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn test_sysvirtual_scripts() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let start = tokio::time::Instant::now();
+        sys.set_runs(vec![
+            Script::default()
+                .out("a")
+                .wait(Duration::seconds(2))
+                .exit(ExitSts::Success),
+            Script::default().err("b").exit(ExitSts::Code(1)),
+        ]);
+        let first = sys.run_command(Cmd::default())?.collect::<Vec<_>>().await;
+        assert_eq!(
+            first,
+            vec![Item::Stdout("a".into()), Item::Done(Ok(ExitSts::Success))]
+        );
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(2));
+        let second = sys.run_command(Cmd::default())?.collect::<Vec<_>>().await;
+        assert_eq!(
+            second,
+            vec![Item::Stderr("b".into()), Item::Done(Ok(ExitSts::Code(1)))]
+        );
+        // No scripts left:
+        let third = sys.run_command(Cmd::default())?.collect::<Vec<_>>().await;
+        assert_eq!(third, vec![]);
+        sys.set_user_events(vec![(Duration::seconds(1), UserEvent::Quit)]);
+        let events = sys.user_stream().unwrap().collect::<Vec<_>>().await;
+        assert_eq!(events, vec![UserEvent::Quit]);
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(3));
+        sys.set_widths(vec![Some(40), None, Some(20)]);
+        let widths = (0..4).map(|_| sys.get_width()).collect::<Vec<_>>();
+        assert_eq!(widths, vec![Some(40), None, Some(20), Some(20)]);
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sysvirtual_clock() {
+        let sys = SysVirtual::default().with_virtual_clock();
+        assert_eq!(sys.now(), Instant::default());
+        assert_eq!(sys.now(), Instant::default());
+        tokio::time::advance(std::time::Duration::from_millis(2500)).await;
+        assert_eq!(
+            sys.now(),
+            &Instant::default() + &Duration::milliseconds(2500)
+        );
+        // Clones share the clock:
+        let clone = sys.clone();
+        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+        assert_eq!(sys.now(), clone.now());
+        assert_eq!(
+            clone.now(),
+            &Instant::default() + &Duration::milliseconds(3500)
+        );
+    }
+
+    #[test]
+    fn test_sysvirtual_files() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        let path = Path::new("dir/file");
+        assert!(sys.read_file(path).is_err());
+        sys.write_file(path, "contents")?;
+        assert_eq!(sys.read_file(path)?, "contents");
+        assert_eq!(sys.files(), vec![path]);
+        sys.append_file(path, "\nmore")?;
+        assert_eq!(sys.read_file(path)?, "contents\nmore");
+        assert!(sys.append_file(Path::new("other"), "").is_err());
+        sys.remove_file(path)?;
+        assert!(sys.remove_file(path).is_err());
+        assert!(sys.files().is_empty());
+        Ok(())
+    }
+
     // A couple of tests for SysReal for minimal coverage
 
     #[test]
@@ -171,7 +588,7 @@ pub mod test {
         let sys = SysReal::default();
         let now = sys.now();
         let now2 = sys.now();
-        assert!(&now2 >= &now);
+        assert!(now2 >= now);
     }
 
     #[tokio::test]
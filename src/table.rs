@@ -0,0 +1,256 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Comparison of whitespace-aligned tables, like the ones printed by
+//! `docker ps` or `df -h`.
+//!
+//! The first non-empty line is the header. Columns are separated by
+//! the character positions that are blank in all lines, which keeps
+//! headers like `CONTAINER ID` and values like `Up 2 hours` in a
+//! single column. Rows are matched across runs by the value of a key
+//! column, and compared cell by cell.
+
+use std::collections::HashMap;
+
+/// A parsed table.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Table {
+    columns: Vec<String>,
+    /// The rows, as the key, the cells and the original line.
+    rows: Vec<(String, Vec<String>, String)>,
+    /// If the key column was not in the header.
+    missing_key: bool,
+}
+
+/// Returns the ranges of characters of each column.
+fn column_ranges(lines: &[Vec<char>]) -> Vec<(usize, usize)> {
+    let width = lines.iter().map(Vec::len).max().unwrap_or(0);
+    let blank = (0..width)
+        .map(|i| {
+            lines
+                .iter()
+                .all(|l| l.get(i).is_none_or(|c| c.is_whitespace()))
+        })
+        .collect::<Vec<_>>();
+    let mut ranges = vec![];
+    let mut start = None;
+    for (i, is_blank) in blank.iter().chain([true].iter()).enumerate() {
+        match (start, is_blank) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                ranges.push((s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    // Columns without a header are part of the previous one:
+    let header = &lines[0];
+    let mut merged: Vec<(usize, usize)> = vec![];
+    for (s, e) in ranges {
+        let named = (s..e).any(|i| header.get(i).is_some_and(|c| !c.is_whitespace()));
+        match merged.last_mut() {
+            Some(last) if !named => last.1 = e,
+            _ => merged.push((s, e)),
+        }
+    }
+    // Each column extends until the next one:
+    let mut columns = merged.clone();
+    for i in 1..columns.len() {
+        columns[i - 1].1 = merged[i].0;
+    }
+    if let Some(last) = columns.last_mut() {
+        last.1 = width;
+    }
+    columns
+}
+
+fn cell(line: &[char], range: (usize, usize)) -> String {
+    let end = range.1.min(line.len());
+    let start = range.0.min(end);
+    line[start..end]
+        .iter()
+        .collect::<String>()
+        .trim()
+        .to_owned()
+}
+
+impl Table {
+    /// Parses the lines, keying the rows by the `key_column`, or by the
+    /// first column if it's not in the header.
+    fn parse(lines: &[String], key_column: Option<&str>) -> Table {
+        let lines = lines
+            .iter()
+            .filter(|l| !l.trim().is_empty())
+            .map(|l| l.chars().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        if lines.is_empty() {
+            return Table::default();
+        }
+        let ranges = column_ranges(&lines);
+        let columns = ranges
+            .iter()
+            .map(|range| cell(&lines[0], *range))
+            .collect::<Vec<_>>();
+        let ikey = key_column.map(|key| columns.iter().position(|c| c == key));
+        let missing_key = ikey == Some(None);
+        let ikey = ikey.flatten().unwrap_or(0);
+        let mut seen = HashMap::<String, usize>::new();
+        let rows = lines[1..]
+            .iter()
+            .map(|line| {
+                let cells = ranges
+                    .iter()
+                    .map(|range| cell(line, *range))
+                    .collect::<Vec<_>>();
+                let mut key = cells.get(ikey).cloned().unwrap_or_default();
+                // Repeated keys are told apart by their position:
+                let count = seen.entry(key.clone()).or_default();
+                *count += 1;
+                if *count > 1 {
+                    key = format!("{key}#{count}");
+                }
+                let line = line.iter().collect::<String>().trim().to_owned();
+                (key, cells, line)
+            })
+            .collect();
+        Table {
+            columns,
+            rows,
+            missing_key,
+        }
+    }
+
+    fn get<'a>(&'a self, cells: &'a [String], column: &str) -> &'a str {
+        (self.columns.iter().position(|c| c == column))
+            .and_then(|i| cells.get(i))
+            .map_or("", String::as_str)
+    }
+}
+
+/// Compares the tables row by row, returning the `changed`, `added`
+/// and `removed` entries, and a warning if the key column is not in
+/// the header of the new table.
+pub fn diff(
+    old: &[String],
+    new: &[String],
+    key_column: Option<&str>,
+) -> (Vec<String>, Option<String>) {
+    let old = Table::parse(old, key_column);
+    let new = Table::parse(new, key_column);
+    let warning = (new.missing_key).then(|| {
+        let key = key_column.unwrap_or_default();
+        let first = new.columns.first().map_or("", String::as_str);
+        format!("key column {key} is not in the header, using {first}")
+    });
+    let old_rows = (old.rows.iter())
+        .map(|(key, cells, _)| (key.as_str(), cells))
+        .collect::<HashMap<_, _>>();
+    let mut entries = vec![];
+    let mut columns = new.columns.clone();
+    columns.extend(
+        old.columns
+            .iter()
+            .filter(|c| !new.columns.contains(c))
+            .cloned(),
+    );
+    for (key, cells, line) in &new.rows {
+        let Some(old_cells) = old_rows.get(key.as_str()) else {
+            entries.push(format!("added {key}: {line}"));
+            continue;
+        };
+        let changes = columns
+            .iter()
+            .filter_map(|column| {
+                let before = old.get(old_cells, column);
+                let after = new.get(cells, column);
+                (before != after).then(|| format!("{column} {before} → {after}"))
+            })
+            .collect::<Vec<_>>();
+        if !changes.is_empty() {
+            entries.push(format!("changed {key}: {}", changes.join(", ")));
+        }
+    }
+    let new_keys = (new.rows.iter())
+        .map(|(key, _, _)| key.as_str())
+        .collect::<Vec<_>>();
+    for (key, _, line) in &old.rows {
+        if !new_keys.contains(&key.as_str()) {
+            entries.push(format!("removed {key}: {line}"));
+        }
+    }
+    (entries, warning)
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(String::from).collect()
+    }
+
+    const PODS: &str = "\
+NAME    READY   STATUS    RESTARTS   AGE
+web-1   1/1     Running   0          2d
+web-2   1/1     Running   1          2d";
+
+    #[test]
+    fn test_parse() {
+        let table = Table::parse(&lines(PODS), None);
+        assert_eq!(
+            table.columns,
+            vec!["NAME", "READY", "STATUS", "RESTARTS", "AGE"]
+        );
+        assert_eq!(table.rows[1].0, "web-2");
+        assert_eq!(table.rows[1].1, vec!["web-2", "1/1", "Running", "1", "2d"]);
+        let docker = "\
+CONTAINER ID   STATUS         NAMES
+a1b2c3d4e5f6   Up 2 hours     web
+f6e5d4c3b2a1   Exited (0) 1   db";
+        let table = Table::parse(&lines(docker), Some("NAMES"));
+        assert_eq!(table.columns, vec!["CONTAINER ID", "STATUS", "NAMES"]);
+        assert_eq!(table.rows[0].0, "web");
+        assert_eq!(table.rows[1].1[1], "Exited (0) 1");
+        assert!(!table.missing_key);
+        assert_eq!(Table::parse(&[], None), Table::default());
+        assert_eq!(Table::parse(&[], Some("NAMES")), Table::default());
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff(&lines(PODS), &lines(PODS), None), (vec![], None));
+        let new = "\
+NAME    READY   STATUS             RESTARTS   AGE
+web-2   0/1     CrashLoopBackOff   2          2d
+web-3   1/1     Running            0          1m";
+        assert_eq!(
+            diff(&lines(PODS), &lines(new), Some("NAME")).0,
+            vec![
+                "changed web-2: READY 1/1 → 0/1, STATUS Running → CrashLoopBackOff, RESTARTS 1 → 2",
+                "added web-3: web-3   1/1     Running            0          1m",
+                "removed web-1: web-1   1/1     Running   0          2d",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_keys() {
+        let old = lines("A  B\nx  1\nx  2");
+        let new = lines("A  B\nx  1\nx  3");
+        assert_eq!(diff(&old, &new, None).0, vec!["changed x#2: B 2 → 3"]);
+    }
+
+    #[test]
+    fn test_missing_key() {
+        let (entries, warning) = diff(&lines(PODS), &lines(PODS), Some("NAMES"));
+        assert_eq!(entries, Vec::<String>::new());
+        assert_eq!(
+            warning.as_deref(),
+            Some("key column NAMES is not in the header, using NAME")
+        );
+    }
+}
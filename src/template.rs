@@ -0,0 +1,155 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Templates with `{name}` placeholders, used for the line prefix and
+//! for the status line.
+//!
+//! `{{` and `}}` are literal braces. Placeholders that render empty
+//! take one of the surrounding spaces with them, so that optional
+//! values don't leave double spaces behind.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder(String),
+}
+
+/// A parsed template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template(Vec<Part>);
+
+impl Template {
+    /// Parses the template, accepting only the given placeholders.
+    pub fn new(template: &str, placeholders: &[&str]) -> Result<Self> {
+        let mut parts = vec![];
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(eyre!("unclosed {{ in template {template}")),
+                        }
+                    }
+                    if !placeholders.contains(&name.as_str()) {
+                        return Err(eyre!(
+                            "invalid placeholder {{{name}}} in template {template}, expected one of {}",
+                            placeholders
+                                .iter()
+                                .map(|p| format!("{{{p}}}"))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        ));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(name));
+                }
+                '}' => {
+                    return Err(eyre!(
+                        "unmatched }} in template {template}, use }}}} for a literal brace"
+                    ));
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template(parts))
+    }
+
+    /// Renders the template, getting the values of the placeholders
+    /// from the `value` function.
+    pub fn render(&self, value: impl Fn(&str) -> String) -> String {
+        let mut output = String::new();
+        let mut skip_space = false;
+        for (i, part) in self.0.iter().enumerate() {
+            match part {
+                Part::Literal(literal) => {
+                    let literal = match literal.strip_prefix(' ') {
+                        Some(rest) if skip_space => rest,
+                        _ => literal,
+                    };
+                    output.push_str(literal);
+                    skip_space = false;
+                }
+                Part::Placeholder(name) => {
+                    let value = value(name);
+                    if !value.is_empty() {
+                        output.push_str(&value);
+                        skip_space = false;
+                        continue;
+                    }
+                    let next_space = matches!(
+                        self.0.get(i + 1),
+                        Some(Part::Literal(next)) if next.starts_with(' ')
+                    );
+                    if next_space && (output.is_empty() || output.ends_with(' ')) {
+                        skip_space = true;
+                    } else if !next_space && output.ends_with(' ') {
+                        output.pop();
+                    }
+                }
+            }
+        }
+        output
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(template: &str, a: &str) -> Result<String> {
+        let template = Template::new(template, &["a", "b"])?;
+        Ok(template.render(|name| match name {
+            "a" => a.to_owned(),
+            _ => "B".to_owned(),
+        }))
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        assert_eq!(render("{a} and {b}", "A")?, "A and B");
+        assert_eq!(render("{{a}} {a}}}", "A")?, "{a} A}");
+        assert_eq!(render("", "A")?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_placeholders() -> Result<()> {
+        assert_eq!(render("<O> {a}", "")?, "<O>");
+        assert_eq!(render("{a} {b}", "")?, "B");
+        assert_eq!(render("x {a} {b}", "")?, "x B");
+        assert_eq!(render("[ogle {a}]", "")?, "[ogle]");
+        assert_eq!(render("x{a} y", "")?, "x y");
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Template::new("{c}", &["a"]).is_err());
+        assert!(Template::new("{a", &["a"]).is_err());
+        assert!(Template::new("a}", &["a"]).is_err());
+    }
+}
@@ -7,13 +7,30 @@
 //! This wraps [`crossterm`] at the moment.
 
 use crossterm::{
+    Command,
     cursor::{MoveToColumn, MoveUp},
     execute,
+    style::{ResetColor, SetForegroundColor},
     terminal::{Clear, ClearType, size},
 };
 use std::io::Result;
 use std::io::{Write, stdout};
 
+/// Colors used in the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// Used for the lines the command writes to `stderr`.
+    Red,
+}
+
+impl From<Color> for crossterm::style::Color {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Red => crossterm::style::Color::DarkRed,
+        }
+    }
+}
+
 /// Returns the width of the terminal
 ///
 /// Uses [`crossterm::terminal::size`]
@@ -49,3 +66,54 @@ pub fn write_all(buf: &[u8]) -> Result<()> {
     execute!(stdout(), MoveToColumn(0))?;
     Ok(())
 }
+
+/// Sets the color of the text written from now on, or resets it to
+/// the default color if `None` is provided.
+///
+/// Wraps [`crossterm::style::SetForegroundColor`] and
+/// [`crossterm::style::ResetColor`]
+pub fn set_color(color: Option<Color>) -> Result<()> {
+    match color {
+        Some(color) => execute!(stdout(), SetForegroundColor(color.into())),
+        None => execute!(stdout(), ResetColor),
+    }
+}
+
+// ANSI sequences /////////////////////////////////////////////////////
+
+/// Returns the ANSI sequence of a [`crossterm`] command.
+fn ansi(command: impl Command) -> String {
+    let mut sequence = String::new();
+    // Writing to a String doesn't fail:
+    let _ = command.write_ansi(&mut sequence);
+    sequence
+}
+
+/// Returns the bytes that [`move_cursor_up`] writes.
+pub fn move_cursor_up_ansi(n: u16) -> Vec<u8> {
+    ansi(MoveUp(n)).into_bytes()
+}
+
+/// Returns the bytes that [`clear_line`] writes.
+pub fn clear_line_ansi() -> Vec<u8> {
+    format!(
+        "{}{}",
+        ansi(Clear(ClearType::CurrentLine)),
+        ansi(MoveToColumn(0))
+    )
+    .into_bytes()
+}
+
+/// Returns the bytes that [`write_all`] writes.
+pub fn write_all_ansi(buf: &[u8]) -> Vec<u8> {
+    [buf, ansi(MoveToColumn(0)).as_bytes()].concat()
+}
+
+/// Returns the bytes that [`set_color`] writes.
+pub fn set_color_ansi(color: Option<Color>) -> Vec<u8> {
+    match color {
+        Some(color) => ansi(SetForegroundColor(color.into())),
+        None => ansi(ResetColor),
+    }
+    .into_bytes()
+}
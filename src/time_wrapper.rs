@@ -9,7 +9,15 @@
 //! change the underlying crate while minimizing changes to users.
 //!
 //! We use [`chrono`] as the wrapped crate at the moment.
+use chrono::format::Item;
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use std::collections::VecDeque;
 use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio_stream::Stream;
 
 // Instant ///////////////////////////////////////////////////////////
 
@@ -22,12 +30,25 @@ type InstantInner = chrono::DateTime<chrono::Utc>;
 pub struct Instant(InstantInner);
 
 impl Instant {
+    /// Returns the instant and advances it by one second.
     #[cfg(test)]
     pub fn incr(&mut self) -> Self {
         let me = *self;
         *self = &me + &Duration::seconds(1);
         me
     }
+
+    /// Formats the instant with a strftime `pattern`, in UTC or in the
+    /// local timezone.
+    pub fn format(&self, pattern: &str, utc: bool) -> String {
+        if utc {
+            self.0.format(pattern).to_string()
+        } else {
+            chrono::DateTime::<chrono::Local>::from(self.0)
+                .format(pattern)
+                .to_string()
+        }
+    }
 }
 
 impl Default for Instant {
@@ -47,6 +68,16 @@ impl fmt::Display for Instant {
     }
 }
 
+impl std::str::FromStr for Instant {
+    type Err = color_eyre::Report;
+
+    /// Parses RFC 3339 timestamps, like `2025-01-02T03:04:05.678Z`.
+    fn from_str(s: &str) -> Result<Self> {
+        let dt = chrono::DateTime::parse_from_rfc3339(s)?;
+        Ok(Instant(dt.with_timezone(&chrono::Utc)))
+    }
+}
+
 impl From<InstantInner> for Instant {
     fn from(dt: InstantInner) -> Self {
         Self(dt)
@@ -115,18 +146,147 @@ impl Default for Duration {
     }
 }
 
+impl fmt::Display for Duration {
+    /// Shows the duration as `+S.mmms`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let millis = self.num_milliseconds();
+        let sign = if millis < 0 { "-" } else { "+" };
+        let millis = millis.abs();
+        write!(f, "{sign}{}.{:03}s", millis / 1000, millis % 1000)
+    }
+}
+
 impl From<Duration> for std::time::Duration {
     fn from(duration: Duration) -> Self {
         duration.0.to_std().unwrap_or_default()
     }
 }
 
+impl From<std::time::Duration> for Duration {
+    fn from(duration: std::time::Duration) -> Self {
+        chrono::Duration::from_std(duration).map_or(Self::INFINITE, Self)
+    }
+}
+
 impl From<Duration> for tokio::time::Interval {
     fn from(duration: Duration) -> Self {
         tokio::time::interval(duration.into())
     }
 }
 
+// TimeFormat ////////////////////////////////////////////////////////
+
+/// The strftime pattern used when no format is given.
+const DEFAULT_PATTERN: &str = "%Y-%m-%d %H:%M:%S";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Style {
+    /// A strftime pattern.
+    Pattern(String),
+    /// Seconds since the origin.
+    Relative,
+    /// No timestamp at all.
+    None,
+}
+
+/// How timestamps are shown.
+///
+/// Besides strftime patterns, we have the presets `iso`, `time`,
+/// `relative` (to the start of `ogle`) and `none`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeFormat {
+    style: Style,
+    /// Use UTC instead of the local timezone.
+    utc: bool,
+    /// Instant that relative timestamps are relative to.
+    origin: Instant,
+}
+
+impl TimeFormat {
+    pub fn new(format: &str, utc: bool, origin: Instant) -> Result<Self> {
+        let style = match format {
+            "iso" => Style::Pattern("%Y-%m-%dT%H:%M:%S%:z".to_owned()),
+            "time" => Style::Pattern("%H:%M:%S".to_owned()),
+            "relative" => Style::Relative,
+            "none" => Style::None,
+            pattern => {
+                let items = chrono::format::StrftimeItems::new(pattern);
+                if pattern.is_empty() || items.into_iter().any(|i| i == Item::Error) {
+                    return Err(eyre!(
+                        "invalid time format {pattern}, expected a strftime pattern or one of iso, time, relative, none"
+                    ));
+                }
+                Style::Pattern(pattern.to_owned())
+            }
+        };
+        Ok(Self { style, utc, origin })
+    }
+
+    /// Formats the instant; returns `None` if timestamps are disabled.
+    pub fn format(&self, instant: &Instant) -> Option<String> {
+        match &self.style {
+            Style::Pattern(pattern) => Some(instant.format(pattern, self.utc)),
+            Style::Relative => Some((instant - &self.origin).to_string()),
+            Style::None => None,
+        }
+    }
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        Self {
+            style: Style::Pattern(DEFAULT_PATTERN.to_owned()),
+            utc: false,
+            origin: Instant::default(),
+        }
+    }
+}
+
+// Timeline //////////////////////////////////////////////////////////
+
+/// A stream that yields each item after waiting for its delay, in
+/// tokio time; used to script the virtual streams in tests.
+#[derive(Debug)]
+pub struct Timeline<T> {
+    items: VecDeque<(Duration, T)>,
+    sleep: Option<Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<T> Default for Timeline<T> {
+    fn default() -> Self {
+        Self {
+            items: VecDeque::default(),
+            sleep: None,
+        }
+    }
+}
+
+impl<T> From<VecDeque<(Duration, T)>> for Timeline<T> {
+    fn from(items: VecDeque<(Duration, T)>) -> Self {
+        Self { items, sleep: None }
+    }
+}
+
+impl<T: Unpin> Stream for Timeline<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let Some((delay, _)) = this.items.front() else {
+            return Poll::Ready(None);
+        };
+        if *delay > Duration::default() {
+            let delay = std::time::Duration::from(*delay);
+            let sleep = (this.sleep).get_or_insert_with(|| Box::pin(tokio::time::sleep(delay)));
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            this.sleep = None;
+        }
+        Poll::Ready(this.items.pop_front().map(|(_, item)| item))
+    }
+}
+
 // Tests /////////////////////////////////////////////////////////////
 
 #[cfg(test)]
@@ -135,6 +295,8 @@ mod test {
 
     use crate::sys::SysApi;
     use crate::sys::SysReal;
+    use crate::sys::SysVirtual;
+    use std::str::FromStr;
 
     #[test]
     fn basic_instant() {
@@ -144,7 +306,7 @@ mod test {
         let now2 = sys.now();
         assert!(&now2 - &now < ten);
         let now3 = &now2 + &ten;
-        assert!(&now3 > &now2);
+        assert!(now3 > now2);
     }
 
     #[test]
@@ -155,6 +317,49 @@ mod test {
         assert_eq!(string, "1970-01-01 00:00:00");
     }
 
+    #[test]
+    fn time_format_presets() -> Result<()> {
+        let sys = SysVirtual::default();
+        let origin = sys.now();
+        let now = sys.now();
+        let format = |f: &str| TimeFormat::new(f, true, origin).map(|tf| tf.format(&now));
+        assert_eq!(format("iso")?.as_deref(), Some("1970-01-01T00:00:01+00:00"));
+        assert_eq!(format("time")?.as_deref(), Some("00:00:01"));
+        assert_eq!(format("relative")?.as_deref(), Some("+1.000s"));
+        assert_eq!(format("none")?, None);
+        assert_eq!(format("%s %d")?.as_deref(), Some("1 01"));
+        assert!(format("%Q").is_err());
+        assert!(format("").is_err());
+        assert_eq!(
+            TimeFormat::default().format(&now),
+            Some(now.format(DEFAULT_PATTERN, false))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn parse_instant() -> Result<()> {
+        let instant = &Instant::default() + &Duration::milliseconds(1500);
+        assert_eq!(Instant::from_str("1970-01-01T00:00:01.500Z")?, instant);
+        assert_eq!(Instant::from_str("1970-01-01T01:00:01.5+01:00")?, instant);
+        assert!(Instant::from_str("1970-01-01").is_err());
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeline() {
+        use tokio_stream::StreamExt;
+        let start = tokio::time::Instant::now();
+        let items = VecDeque::from([
+            (Duration::default(), 'a'),
+            (Duration::seconds(2), 'b'),
+            (Duration::milliseconds(500), 'c'),
+        ]);
+        let timeline = Timeline::from(items);
+        assert_eq!(timeline.collect::<Vec<_>>().await, vec!['a', 'b', 'c']);
+        assert_eq!(start.elapsed(), std::time::Duration::from_millis(2500));
+    }
+
     #[test]
     fn basic_duration() {
         assert_eq!(Duration::seconds(10).num_seconds(), 10);
@@ -0,0 +1,106 @@
+// Copyright (C) 2025 Leandro Lisboa Penz <lpenz@lpenz.org>
+// This file is subject to the terms and conditions defined in
+// file 'LICENSE', which is part of this source code package.
+
+//! Tracking of a numeric value extracted from the output of the runs.
+
+use color_eyre::Result;
+use color_eyre::eyre::eyre;
+use regex::Regex;
+use std::collections::VecDeque;
+
+use crate::time_wrapper::Instant;
+
+/// Maximum number of values we keep, which is also the width of the
+/// sparkline.
+const MAX_VALUES: usize = 30;
+
+/// Extracts a value from the output of each run using the single
+/// capture group of a regex, and keeps the series of the values.
+#[derive(Debug)]
+pub struct Tracker {
+    regex: Regex,
+    /// Value found in the current run; the last match wins.
+    current: Option<f64>,
+    /// Values of the previous runs, with the time they were found.
+    series: VecDeque<(Instant, f64)>,
+}
+
+impl Tracker {
+    pub fn new(regex: Regex) -> Result<Self> {
+        if regex.captures_len() != 2 {
+            return Err(eyre!(
+                "tracking regex {} must have exactly one capture group",
+                regex
+            ));
+        }
+        Ok(Self {
+            regex,
+            current: None,
+            series: VecDeque::default(),
+        })
+    }
+
+    /// Name of the capture group, if it has one.
+    pub fn name(&self) -> Option<&str> {
+        self.regex.capture_names().flatten().next()
+    }
+
+    pub fn push_line(&mut self, line: &str) {
+        if let Some(value) = (self.regex.captures(line))
+            .and_then(|captures| captures.get(1))
+            .and_then(|m| m.as_str().trim().parse().ok())
+        {
+            self.current = Some(value);
+        }
+    }
+
+    /// Ends the current run, adding its value to the series; returns
+    /// the value, if the run had it.
+    pub fn finish(&mut self, time: Instant) -> Option<f64> {
+        let value = self.current.take()?;
+        if self.series.len() == MAX_VALUES {
+            self.series.pop_front();
+        }
+        self.series.push_back((time, value));
+        Some(value)
+    }
+
+    pub fn values(&self) -> Vec<f64> {
+        self.series.iter().map(|(_, value)| *value).collect()
+    }
+}
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_tracker() -> Result<()> {
+        assert!(Tracker::new(Regex::new(r"\d+")?).is_err());
+        let mut tracker = Tracker::new(Regex::new(r"depth: (\d+)")?)?;
+        assert_eq!(tracker.name(), None);
+        let mut now = Instant::default();
+        tracker.push_line("depth: 3");
+        tracker.push_line("other");
+        tracker.push_line("depth: 5");
+        assert_eq!(tracker.finish(now.incr()), Some(5.0));
+        // Runs without the value are skipped:
+        tracker.push_line("depth: x");
+        assert_eq!(tracker.finish(now.incr()), None);
+        tracker.push_line("depth: 2");
+        tracker.finish(now.incr());
+        assert_eq!(tracker.values(), vec![5.0, 2.0]);
+        for i in 0..MAX_VALUES {
+            tracker.push_line(&format!("depth: {i}"));
+            tracker.finish(now.incr());
+        }
+        assert_eq!(tracker.values().len(), MAX_VALUES);
+        assert_eq!(tracker.values()[0], 0.0);
+        let tracker = Tracker::new(Regex::new(r"depth: (?<depth>\d+)")?)?;
+        assert_eq!(tracker.name(), Some("depth"));
+        Ok(())
+    }
+}
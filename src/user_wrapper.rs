@@ -21,6 +21,8 @@ use tokio_stream::Stream;
 use tracing::info;
 use tracing::instrument;
 
+use crate::time_wrapper::Timeline;
+
 /// An event coming from the user
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UserEvent {
@@ -61,13 +63,18 @@ impl TryFrom<&Event> for UserEvent {
 /// keypress in a tokio stream.
 ///
 /// Also provides a virtual implementation for use in tests.
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub enum UserStream {
     /// A real implementation that gets KeyEvents from an EventStream
     Real(EventStream),
-    /// A virtual implementation that doesn't do anything.
-    #[default]
-    Virtual,
+    /// A virtual implementation that yields scripted events.
+    Virtual(Timeline<UserEvent>),
+}
+
+impl Default for UserStream {
+    fn default() -> Self {
+        UserStream::Virtual(Timeline::default())
+    }
 }
 
 impl UserStream {
@@ -81,8 +88,9 @@ impl UserStream {
         }
     }
 
-    pub fn new_virtual() -> UserStream {
-        UserStream::Virtual
+    /// Yields the `events`, each after its delay.
+    pub fn new_virtual(events: Timeline<UserEvent>) -> UserStream {
+        UserStream::Virtual(events)
     }
 }
 
@@ -126,7 +134,7 @@ impl Stream for UserStream {
                     Poll::Pending => Poll::Pending,
                 }
             }
-            UserStream::Virtual => Poll::Ready(None),
+            UserStream::Virtual(events) => Pin::new(events).poll_next(cx),
         }
     }
 }
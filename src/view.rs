@@ -3,26 +3,55 @@
 // file 'LICENSE', which is part of this source code package.
 
 use pin_project::pin_project;
+use std::collections::HashMap;
 use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio_stream::Stream;
 
+use crate::alert::Alert;
+use crate::change::ChangePolicy;
+use crate::cli::Baseline;
+use crate::cli::Compare;
+use crate::cli::LineTimestamps;
+use crate::cli::SummaryFormat;
+use crate::differ;
 use crate::differ::Differ;
 use crate::engine::EData;
 use crate::engine::EItem;
 use crate::engine::Engine;
+use crate::history::History;
+use crate::json::Selector;
+use crate::misc::Ofmt;
+use crate::numeric::Deltas;
+use crate::numeric::Tolerance;
 use crate::output::ClearLine;
 use crate::output::MoveCursorUp;
 use crate::output::OutputCommand;
+use crate::output::SetColor;
 use crate::output::WriteAll;
 use crate::process_wrapper::Cmd;
+use crate::process_wrapper::ExitSts;
+use crate::progbar::Counters;
+use crate::progbar::bar;
 use crate::progbar::progbar_running;
 use crate::progbar::progbar_sleeping;
+use crate::progbar::sleep_left;
 use crate::progbar::spinner_get;
+use crate::progbar::status_template;
+use crate::snapshot::Snapshot;
+use crate::summary::Summary;
 use crate::sys::SysApi;
+use crate::template::Template;
+use crate::term_wrapper::Color;
 use crate::time_wrapper::Duration;
 use crate::time_wrapper::Instant;
+use crate::track::Tracker;
+
+/// Maximum width of the status line, used when we don't know the
+/// width of the terminal.
+const STATUS_WIDTH: usize = 150;
 
 #[derive(Debug, Clone, Copy)]
 pub enum State {
@@ -34,19 +63,97 @@ pub enum State {
     },
 }
 
+/// The output stream of the command that printed a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Stdout,
+    Stderr,
+}
+
+/// Output of an overlapping run, stored until the run is done so that
+/// we can compare runs in completion order.
+#[derive(Debug, Default)]
+struct RunBuffer {
+    start: Instant,
+    lines: Vec<(Instant, Source, String)>,
+}
+
+/// Optional behaviors of the [`View`].
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Maximum number of runs in flight; runs are buffered if > 1.
+    pub max_concurrent: usize,
+    /// Output streams that are compared to detect changes.
+    pub compare: Compare,
+    /// Facets of the runs that count as changes.
+    pub policy: ChangePolicy,
+    /// How the lines of the runs are compared.
+    pub mode: differ::Mode,
+    /// Reduces the JSON documents before comparing, in JSON mode.
+    pub selector: Option<Selector>,
+    /// Column that identifies the rows, in table mode.
+    pub key_column: Option<String>,
+    /// Tolerance used when comparing the numbers in the lines.
+    pub tolerance: Option<Tolerance>,
+    /// Annotate the numbers in stdout with their change since the
+    /// previous run.
+    pub delta: bool,
+    /// Tracks a value extracted from stdout.
+    pub tracker: Option<Tracker>,
+    /// Alert on a threshold of the tracked value.
+    pub alert: Option<Alert>,
+    /// The run that the other runs are compared against.
+    pub baseline: Baseline,
+    /// Lines that the stdout of all runs are compared against.
+    pub expect: Option<Vec<String>>,
+    /// Exit when stdout matches `expect`.
+    pub until_expected: bool,
+    /// Exit after this number of consecutive unchanged runs.
+    pub until_stable: Option<u32>,
+    /// Prefix the lines of the command with their arrival time.
+    pub line_timestamps: Option<LineTimestamps>,
+    /// Format of the lines that we print.
+    pub ofmt: Ofmt,
+    /// Template of the status line, replacing the default one.
+    pub status: Option<Template>,
+    /// Print a summary of the runs when exiting.
+    pub summary: Option<SummaryFormat>,
+    /// Stores the changed runs in a directory.
+    pub history: Option<History>,
+    /// File where we save the state on each change and when exiting.
+    pub state_file: Option<PathBuf>,
+    /// State loaded from the `state_file` at startup.
+    pub resume: Option<Snapshot>,
+}
+
 #[pin_project(project = ViewProjection)]
 pub struct View<SI: SysApi> {
     // Configuration parameters:
     cmd: Cmd,
     refresh: Duration,
     sleep: Duration,
+    options: Options,
     /// The engine that streams the all events.
     engine: Engine<SI>,
     /// Some engine events generate more than one item; store them
     /// here and yield them in the next calls.
     pending: VecDeque<OutputCommand>,
-    /// The differ that stores the lines so that we can compare runs.
+    /// The differ that stores the stdout lines so that we can compare
+    /// runs.
     differ: Differ,
+    /// The differ that stores the stderr lines.
+    differ_err: Differ,
+    /// Annotates the numbers in the stdout lines, with --delta.
+    deltas: Option<Deltas>,
+    /// Start of the run being compared.
+    run_start: Instant,
+    /// Arrival times of the stdout lines not printed yet, with
+    /// --line-timestamps.
+    times_out: VecDeque<Instant>,
+    /// Arrival times of the stderr lines not printed yet.
+    times_err: VecDeque<Instant>,
+    /// If the current run has changed and is being printed.
+    run_changed: bool,
     /// Spinner state
     spinner: char,
     start: Instant, // can be start of running or sleep
@@ -55,30 +162,86 @@ pub struct View<SI: SysApi> {
     printed_status: bool,
     /// Total number of runs.
     total_runs: u32,
+    /// Exit status of the last run.
+    last_exit: Option<ExitSts>,
+    /// Statistics of the runs, with --summary; taken when printed.
+    summary: Option<Summary>,
     /// Current number of unchanged runs.
     unchanged_runs: u32,
+    /// Number of runs in flight.
+    inflight: usize,
+    /// Buffered output of the overlapping runs in flight, by run id.
+    buffers: HashMap<u32, RunBuffer>,
     /// Current state
     state: State,
+    /// Stop yielding items after the pending ones.
+    exit: bool,
+    /// If we have done what we do when exiting.
+    finished: bool,
 }
 
 impl<SI: SysApi> View<SI> {
-    pub fn new(cmd: Cmd, refresh: Duration, sleep: Duration, engine: Engine<SI>) -> Self {
+    pub fn new(
+        cmd: Cmd,
+        refresh: Duration,
+        sleep: Duration,
+        mut options: Options,
+        engine: Engine<SI>,
+    ) -> Self {
+        let mode = options.mode;
+        let resume = options.resume.take();
+        let first = options.baseline == Baseline::First;
+        let differ = match (options.expect.take(), &resume) {
+            (Some(expect), _) => Differ::pinned(mode, expect),
+            (None, Some(snapshot)) if first => Differ::pinned(mode, snapshot.stdout.clone()),
+            (None, Some(snapshot)) => Differ::resumed(mode, snapshot.stdout.clone()),
+            (None, None) => Differ::new(mode),
+        }
+        .with_tolerance(options.tolerance)
+        .with_selector(options.selector.take())
+        .with_key_column(options.key_column.take());
+        // stderr is never JSON or a table
+        let mode_err = match mode {
+            differ::Mode::Json | differ::Mode::Table => differ::Mode::Lines,
+            mode => mode,
+        };
+        let differ_err = match &resume {
+            Some(snapshot) if first => Differ::pinned(mode_err, snapshot.stderr.clone()),
+            Some(snapshot) => Differ::resumed(mode_err, snapshot.stderr.clone()),
+            None => Differ::new(mode_err),
+        }
+        .with_tolerance(options.tolerance);
+        let deltas = options.delta.then(Deltas::default);
+        let summary = options.summary.map(|_| Summary::default());
         View {
             cmd,
             refresh,
             sleep,
+            options,
             engine,
             pending: VecDeque::default(),
-            differ: Differ::default(),
+            differ,
+            differ_err,
+            deltas,
+            run_start: Instant::default(),
+            times_out: VecDeque::default(),
+            times_err: VecDeque::default(),
+            run_changed: false,
             spinner: '-',
             start: Instant::default(),
             duration: None,
             printed_status: false,
-            total_runs: 0,
-            unchanged_runs: 0,
+            total_runs: resume.as_ref().map_or(0, |s| s.total_runs),
+            last_exit: None,
+            summary,
+            unchanged_runs: resume.as_ref().map_or(0, |s| s.unchanged_runs),
+            inflight: 0,
+            buffers: HashMap::default(),
             state: State::Sleeping {
                 deadline: Default::default(),
             },
+            exit: false,
+            finished: false,
         }
     }
 }
@@ -105,34 +268,400 @@ impl<SI: SysApi> ViewProjection<'_, SI> {
         *self.printed_status = false;
     }
 
-    fn process_line(&mut self, line: String) {
-        self.differ.push(line);
-        let mut differ = std::mem::take(self.differ);
-        if differ.has_changed() {
-            for line in &mut differ {
-                self.println(line);
+    fn println_err(&mut self, s: String) {
+        self.status_maybe_clear();
+        self.pending
+            .push_back(OutputCommand::SetColor(SetColor(Some(Color::Red))));
+        self.write_line(s);
+        self.pending
+            .push_back(OutputCommand::SetColor(SetColor(None)));
+        *self.printed_status = false;
+    }
+
+    fn process_line(&mut self, time: Instant, source: Source, line: String) {
+        if self.options.line_timestamps.is_some() {
+            match source {
+                Source::Stdout => self.times_out.push_back(time),
+                Source::Stderr => self.times_err.push_back(time),
+            }
+        }
+        match source {
+            Source::Stdout => {
+                if let Some(deltas) = self.deltas {
+                    deltas.push(&line);
+                }
+                if let Some(tracker) = &mut self.options.tracker {
+                    tracker.push_line(&line);
+                }
+                self.differ.push(line)
+            }
+            Source::Stderr => self.differ_err.push(line),
+        }
+        self.flush();
+    }
+
+    /// Returns true if any of the differs that we are comparing has
+    /// changed.
+    fn compared_changed(&self) -> bool {
+        let out = self.differ.has_changed();
+        let err = self.differ_err.has_changed();
+        match self.options.compare {
+            Compare::Stdout => out,
+            Compare::Stderr => err,
+            Compare::Both => out || err,
+        }
+    }
+
+    /// Prints the lines the differs have for us, if the current run
+    /// has changed.
+    fn flush(&mut self) {
+        if !*self.run_changed {
+            if !self.options.policy.output() || !self.compared_changed() {
+                return;
             }
+            self.set_run_changed();
+        }
+        let mut differ = std::mem::take(self.differ);
+        for line in &mut differ {
+            let line = match self.deltas {
+                Some(deltas) => deltas.annotate(line),
+                None => line,
+            };
+            let time = self.times_out.pop_front();
+            let line = self.stamp(time, line);
+            self.println(line);
         }
         *self.differ = differ;
+        let mut differ = std::mem::take(self.differ_err);
+        for line in &mut differ {
+            let time = self.times_err.pop_front();
+            let line = self.stamp(time, line);
+            self.println_err(line);
+        }
+        *self.differ_err = differ;
+    }
+
+    /// Prefixes the line with its arrival time, with --line-timestamps.
+    fn stamp(&self, time: Option<Instant>, line: String) -> String {
+        match (self.options.line_timestamps, time) {
+            (Some(LineTimestamps::Absolute), Some(time)) => {
+                match self.options.ofmt.time.format(&time) {
+                    Some(time) => format!("{time} {line}"),
+                    None => line,
+                }
+            }
+            (Some(LineTimestamps::Relative), Some(time)) => {
+                format!("{} {line}", &time - self.run_start)
+            }
+            _ => line,
+        }
+    }
+
+    /// Marks the current run as changed, which prints the header and
+    /// makes the differs yield all lines of the run.
+    fn set_run_changed(&mut self) {
+        *self.run_changed = true;
+        self.println(ofmt_timeless!(self.options.ofmt, "+ {}", self.cmd));
+        self.differ.show();
+        self.differ_err.show();
+    }
+
+    /// Gets the differs ready for a new run.
+    fn compare_start(&mut self, start: Instant) {
+        self.differ.reset();
+        self.differ_err.reset();
+        if let Some(deltas) = self.deltas {
+            deltas.reset(start);
+        }
+        *self.run_start = start;
+        self.times_out.clear();
+        self.times_err.clear();
+        *self.run_changed = false;
+        if *self.total_runs == 0 {
+            // The first run is always shown
+            self.set_run_changed();
+        }
+    }
+
+    fn counters(&self) -> Counters {
+        Counters {
+            unchanged_runs: *self.unchanged_runs,
+            until_stable: self.options.until_stable,
+            inflight: (self.options.max_concurrent > 1).then_some(*self.inflight),
+            tracked: (self.options.tracker.as_ref())
+                .map(Tracker::values)
+                .unwrap_or_default(),
+            runs: *self.total_runs,
+            exit: self.last_exit.clone(),
+        }
+    }
+
+    fn run_start(&mut self, now: Instant, run: Option<u32>) {
+        if self.last_exit.is_none() && *self.inflight == 0 {
+            if *self.total_runs == 0 {
+                self.println(ofmt!(self.options.ofmt, &now, "start execution"));
+            } else {
+                self.println(ofmt!(
+                    self.options.ofmt,
+                    &now,
+                    "resume execution after {} runs",
+                    self.total_runs
+                ));
+            }
+        }
+        *self.inflight += 1;
+        *self.start = now;
+        self.status_update_running(now);
+        match run {
+            Some(run) if self.options.max_concurrent > 1 => {
+                let buffer = RunBuffer {
+                    start: now,
+                    lines: vec![],
+                };
+                self.buffers.insert(run, buffer);
+            }
+            _ => {
+                self.compare_start(now);
+            }
+        }
+    }
+
+    /// Forgets the run that failed with an I/O error, along with the
+    /// lines we were holding for it.
+    fn run_failed(&mut self, run: Option<u32>) {
+        if let Some(run) = run {
+            *self.inflight = self.inflight.saturating_sub(1);
+            self.buffers.remove(&run);
+        }
+    }
+
+    fn run_line(&mut self, now: Instant, run: Option<u32>, source: Source, line: String) {
+        if let Some(buffer) = run.and_then(|run| self.buffers.get_mut(&run)) {
+            buffer.lines.push((now, source, line));
+        } else {
+            self.process_line(now, source, line);
+        }
+    }
+
+    fn run_done(&mut self, now: Instant, run: Option<u32>, sts: ExitSts) {
+        *self.inflight = self.inflight.saturating_sub(1);
+        let start = if let Some(buffer) = run.and_then(|run| self.buffers.remove(&run)) {
+            // Overlapping run: compare the whole output now
+            self.compare_start(buffer.start);
+            for (time, source, line) in buffer.lines {
+                self.process_line(time, source, line);
+            }
+            buffer.start
+        } else {
+            *self.start
+        };
+        self.differ.finish();
+        self.differ_err.finish();
+        if let Some(warning) = self.differ.take_warning() {
+            self.println(ofmt!(self.options.ofmt, &now, "{}", warning));
+        }
+        let tracked = (self.options.tracker.as_mut()).and_then(|tracker| tracker.finish(now));
+        let duration = &now - &start;
+        if let Some(summary) = self.summary {
+            summary.push(start, now, &sts, self.differ.current());
+        }
+        let facets = self
+            .options
+            .policy
+            .evaluate(self.compared_changed(), &sts, duration);
+        if !facets.is_empty() && !*self.run_changed {
+            self.set_run_changed();
+        }
+        self.flush();
+        if *self.run_changed {
+            let line = if *self.total_runs == 0 || facets.is_empty() {
+                ofmt_timeless!(self.options.ofmt, "subprocess exited with {}", sts)
+            } else {
+                let facets = facets.iter().map(|f| f.to_string()).collect::<Vec<_>>();
+                ofmt_timeless!(
+                    self.options.ofmt,
+                    "subprocess exited with {}, changed: {}",
+                    sts,
+                    facets.join(", ")
+                )
+            };
+            self.println(line);
+            if let Some(history) = &mut self.options.history {
+                let stdout = self.differ.current();
+                let stderr = self.differ_err.current();
+                let sys = self.engine.sys_mut();
+                if let Err(e) = history.push(sys, start, now, &sts, stdout, stderr) {
+                    self.println(ofmt!(
+                        self.options.ofmt,
+                        &now,
+                        "error writing history: {}",
+                        e
+                    ));
+                }
+            }
+        }
+        if let Some(value) = tracked {
+            self.alert_evaluate(now, value);
+        }
+        if *self.total_runs == 0 && self.options.baseline == Baseline::First {
+            self.differ.pin();
+            self.differ_err.pin();
+        }
+        if self.options.until_expected && !self.differ.has_changed() {
+            self.println(ofmt!(
+                self.options.ofmt,
+                &now,
+                "output matches the expected output"
+            ));
+            *self.exit = true;
+        }
+        *self.duration = Some(duration);
+        if *self.inflight == 0 {
+            // Sleeping starts now
+            *self.start = now;
+        }
+        *self.total_runs += 1;
+        *self.last_exit = Some(sts.clone());
+        if !*self.run_changed {
+            *self.unchanged_runs += 1;
+        } else {
+            *self.unchanged_runs = 0;
+        }
+        if let Some(until_stable) = self.options.until_stable
+            && *self.unchanged_runs >= until_stable
+        {
+            self.println(ofmt!(
+                self.options.ofmt,
+                &now,
+                "output stable for {} runs",
+                until_stable
+            ));
+            *self.exit = true;
+        }
+        if *self.run_changed {
+            self.save_state();
+        }
+    }
+
+    /// Saves the state to the state file, if we have one.
+    fn save_state(&mut self) {
+        let Some(path) = &self.options.state_file else {
+            return;
+        };
+        let snapshot = Snapshot {
+            command: self.cmd.to_string(),
+            total_runs: *self.total_runs,
+            unchanged_runs: *self.unchanged_runs,
+            stdout: self.differ.next_reference().to_vec(),
+            stderr: self.differ_err.next_reference().to_vec(),
+        };
+        if let Err(e) = snapshot.save(self.engine.sys_mut(), path) {
+            let line = ofmt_timeless!(self.options.ofmt, "error writing state file: {}", e);
+            self.println(line);
+        }
+    }
+
+    /// Evaluates the alert with the tracked value of a run, acting on
+    /// state changes.
+    fn alert_evaluate(&mut self, now: Instant, value: f64) {
+        let Some(alert) = &mut self.options.alert else {
+            return;
+        };
+        let Some(active) = alert.evaluate(value) else {
+            return;
+        };
+        let alert = alert.clone();
+        if active {
+            self.println(ofmt!(
+                self.options.ofmt,
+                &now,
+                "alert raised: {} (value {})",
+                alert,
+                value
+            ));
+            if alert.bell {
+                self.pending
+                    .push_back(OutputCommand::WriteAll(WriteAll(b"\x07".to_vec())));
+            }
+            if alert.exit {
+                *self.exit = true;
+            }
+        } else {
+            self.println(ofmt!(
+                self.options.ofmt,
+                &now,
+                "alert cleared: {} (value {})",
+                alert,
+                value
+            ));
+        }
+        if let Some(cmd) = alert.hook_cmd(value)
+            && let Err(e) = self.engine.sys_mut().run_hook(cmd)
+        {
+            self.println(ofmt!(
+                self.options.ofmt,
+                &now,
+                "error running alert hook: {}",
+                e
+            ));
+        }
+    }
+
+    /// Writes the status line, in red if the alert is raised.
+    fn write_status(&mut self, status: String) {
+        let alerting = (self.options.alert.as_ref()).is_some_and(Alert::is_active);
+        if alerting {
+            self.pending
+                .push_back(OutputCommand::SetColor(SetColor(Some(Color::Red))));
+        }
+        self.write_line(status);
+        if alerting {
+            self.pending
+                .push_back(OutputCommand::SetColor(SetColor(None)));
+        }
+    }
+
+    /// Returns the width left for the status line after the prefix
+    /// with the time `at`; the line has to fit in the terminal, as we
+    /// clear it by moving the cursor up a single line.
+    fn status_width(&self, at: &Instant) -> usize {
+        let prefix = ofmt!(self.options.ofmt, at, "").chars().count();
+        match self.engine.sys().get_width() {
+            Some(width) => usize::from(width).saturating_sub(prefix).min(STATUS_WIDTH),
+            None => STATUS_WIDTH,
+        }
     }
 
     fn status_update_running(&mut self, now: Instant) {
         self.status_maybe_clear();
         let mut spinner = *self.spinner;
-        self.write_line(ofmt!(
-            &now,
-            "{}",
-            progbar_running(
-                150,                       // width: usize,
-                *self.unchanged_runs,      // runs: u32,
-                &now,                      // now: &Instant,
-                self.start,                // start: &Instant,
-                *self.duration,            // duration: Option<&Duration>,
-                self.refresh,              // refresh: &Duration,
-                spinner_get(&mut spinner)  // spinner: char,
+        let counters = self.counters();
+        let spinner_char = spinner_get(&mut spinner);
+        let width = self.status_width(&now);
+        let status = match &self.options.status {
+            Some(template) => status_template(
+                template,
+                width,
+                &counters,
+                "running",
+                |width| {
+                    let duration = self.duration.unwrap_or_default();
+                    bar(width, &now, self.start, &duration, self.refresh).unwrap()
+                },
+                spinner_char,
+            ),
+            None => progbar_running(
+                width,          // width: usize,
+                &counters,      // counters: &Counters,
+                &now,           // now: &Instant,
+                self.start,     // start: &Instant,
+                *self.duration, // duration: Option<&Duration>,
+                self.refresh,   // refresh: &Duration,
+                spinner_char,   // spinner: char,
             )
-            .unwrap()
-        ));
+            .unwrap(),
+        };
+        self.write_status(ofmt!(self.options.ofmt, &now, "{}", status));
         *self.spinner = spinner;
         *self.printed_status = true;
     }
@@ -140,23 +669,47 @@ impl<SI: SysApi> ViewProjection<'_, SI> {
     fn status_update_sleeping(&mut self, now: Instant, deadline: Instant) {
         self.status_maybe_clear();
         let mut spinner = *self.spinner;
+        let counters = self.counters();
         // Use self.start (when sleep began) instead of now:
-        self.write_line(ofmt!(
-            self.start,
-            "{}",
-            progbar_sleeping(
-                *self.unchanged_runs,
-                self.sleep,
-                &now,
-                &deadline,
-                spinner_get(&mut spinner)
-            )
-        ));
+        let spinner_char = spinner_get(&mut spinner);
+        let width = self.status_width(self.start);
+        let status = match &self.options.status {
+            Some(template) => status_template(
+                template,
+                width,
+                &counters,
+                "sleeping",
+                |_| sleep_left(self.sleep, &now, &deadline).unwrap_or_default(),
+                spinner_char,
+            ),
+            None => progbar_sleeping(&counters, self.sleep, &now, &deadline, spinner_char),
+        };
+        self.write_status(ofmt!(self.options.ofmt, self.start, "{}", status));
         *self.spinner = spinner;
         *self.printed_status = true;
     }
 }
 
+impl<SI: SysApi> ViewProjection<'_, SI> {
+    /// Saves the state and prints the summary, when exiting.
+    fn finish(&mut self) {
+        // The lines of an interrupted run are incomplete:
+        if *self.inflight == 0 {
+            self.save_state();
+        }
+        if let Some(summary) = self.summary.take() {
+            match self.options.summary {
+                Some(SummaryFormat::Json) => self.println(summary.json()),
+                _ => {
+                    for line in summary.text() {
+                        self.println(ofmt_timeless!(self.options.ofmt, "{}", line));
+                    }
+                }
+            }
+        }
+    }
+}
+
 impl<SI: SysApi> Stream for View<SI> {
     type Item = OutputCommand;
 
@@ -165,81 +718,92 @@ impl<SI: SysApi> Stream for View<SI> {
         if let Some(output) = this.pending.pop_front() {
             return Poll::Ready(Some(output));
         }
+        if *this.exit {
+            if !*this.finished {
+                *this.finished = true;
+                this.finish();
+                return self.poll_next(cx);
+            }
+            return Poll::Ready(None);
+        }
         let item = Pin::new(&mut this.engine).poll_next(cx);
         match this.state {
             State::Running => {
                 match item {
                     Poll::Pending => Poll::Pending,
-                    Poll::Ready(None) => Poll::Ready(None),
-                    Poll::Ready(Some(EItem { time: now, data })) => match data {
+                    Poll::Ready(None) => {
+                        *this.exit = true;
+                        self.poll_next(cx)
+                    }
+                    Poll::Ready(Some(EItem {
+                        time: now,
+                        run,
+                        data,
+                    })) => match data {
                         EData::StartSleep(deadline) => {
                             *this.state = State::Sleeping { deadline };
                             self.poll_next(cx)
                         }
+                        EData::StartRun => {
+                            // Overlapping run
+                            this.run_start(now, run);
+                            self.poll_next(cx)
+                        }
                         EData::LineOut(line) => {
-                            this.process_line(line);
+                            this.run_line(now, run, Source::Stdout, line);
                             this.status_update_running(now);
                             self.poll_next(cx)
                         }
                         EData::LineErr(line) => {
-                            this.process_line(line);
+                            this.run_line(now, run, Source::Stderr, line);
                             this.status_update_running(now);
                             self.poll_next(cx)
                         }
                         EData::Msg(msg) => {
-                            this.println(ofmt!(&now, "{}", msg));
-                            this.status_update_running(now);
+                            this.println(ofmt!(this.options.ofmt, &now, "{}", msg));
+                            if *this.inflight > 0 {
+                                this.status_update_running(now);
+                            }
                             self.poll_next(cx)
                         }
                         EData::Done(sts) => {
-                            let line = ofmt_timeless!("subprocess exited with {}", sts);
-                            this.process_line(line);
-                            *this.duration = Some(&now - this.start);
-                            // Sleeping starts now
-                            *this.start = now;
-                            *this.total_runs += 1;
-                            if !this.differ.has_changed() {
-                                *this.unchanged_runs += 1;
-                            } else {
-                                *this.unchanged_runs = 0;
-                            }
+                            this.run_done(now, run, sts);
                             self.poll_next(cx)
                         }
                         EData::Err(e) => {
-                            this.println(ofmt!(&now, "err {:?}", e));
+                            this.println(ofmt!(this.options.ofmt, &now, "err {:?}", e));
+                            this.run_failed(run);
                             self.poll_next(cx)
                         }
                         EData::Tick => {
                             this.status_update_running(now);
                             self.poll_next(cx)
                         }
-                        _ => {
-                            panic!("unexpected data while running: {:?}", data);
-                        }
                     },
                 }
             }
             State::Sleeping { deadline } => match item {
                 Poll::Pending => Poll::Pending,
-                Poll::Ready(None) => Poll::Ready(None),
-                Poll::Ready(Some(EItem { time: now, data })) => match data {
+                Poll::Ready(None) => {
+                    *this.exit = true;
+                    self.poll_next(cx)
+                }
+                Poll::Ready(Some(EItem {
+                    time: now,
+                    run,
+                    data,
+                })) => match data {
                     EData::StartRun => {
-                        if *this.total_runs == 0 {
-                            this.println(ofmt!(&now, "start execution"));
-                        }
-                        this.differ.reset();
-                        *this.start = now;
-                        this.status_update_running(now);
-                        this.process_line(ofmt_timeless!("+ {}", this.cmd));
+                        this.run_start(now, run);
                         *this.state = State::Running;
                         self.poll_next(cx)
                     }
                     EData::Msg(msg) => {
-                        this.println(ofmt!(&now, "{}", msg));
+                        this.println(ofmt!(this.options.ofmt, &now, "{}", msg));
                         self.poll_next(cx)
                     }
                     EData::Err(e) => {
-                        this.println(ofmt!(&now, "err {:?}", e));
+                        this.println(ofmt!(this.options.ofmt, &now, "err {:?}", e));
                         self.poll_next(cx)
                     }
                     EData::Tick => {
@@ -255,3 +819,631 @@ impl<SI: SysApi> Stream for View<SI> {
         }
     }
 }
+
+// Tests /////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cli::ChangeOn;
+    use crate::engine::EngineBuilder;
+    use crate::sys::Script;
+    use crate::sys::SysVirtual;
+    use crate::time_wrapper::TimeFormat;
+    use crate::user_wrapper::UserEvent;
+    use color_eyre::Result;
+    use tokio_stream::StreamExt;
+
+    /// A command yielded by the view, with the time it was yielded.
+    type Timed = (Instant, OutputCommand);
+
+    /// Runs a view of `sys` with the virtual clock until it ends,
+    /// returning the commands.
+    async fn run(sys: SysVirtual, sleep: Duration, options: Options) -> Result<Vec<Timed>> {
+        run_filtered(sys, sleep, options, None).await
+    }
+
+    /// Like [`run`], with the stdout of the runs going through the
+    /// `filter`.
+    async fn run_filtered(
+        sys: SysVirtual,
+        sleep: Duration,
+        mut options: Options,
+        filter: Option<Cmd>,
+    ) -> Result<Vec<Timed>> {
+        let sys = sys.with_virtual_clock();
+        let clock = sys.clone();
+        let cmd = Cmd::from(&["cmd"][..]);
+        let refresh = Duration::milliseconds(250);
+        options.max_concurrent = options.max_concurrent.max(1);
+        options.policy = ChangePolicy::new(&[ChangeOn::Output, ChangeOn::Exit]);
+        // The prefix has no time; the format is for --line-timestamps:
+        let time = TimeFormat::new("%H:%M:%S%.3f", true, Instant::default())?;
+        options.ofmt = Ofmt::new(time, "<O>")?;
+        let engine = EngineBuilder::new(cmd.clone())
+            .with_refresh(refresh)
+            .with_period(sleep)
+            .with_user_input(true)
+            .with_max_concurrent(options.max_concurrent)
+            .with_filter(filter)
+            .build(sys)?;
+        let view = View::new(cmd, refresh, sleep, options, engine);
+        Ok(view.map(|command| (clock.now(), command)).collect().await)
+    }
+
+    /// Returns the lines written by the commands, with their times.
+    fn written(commands: &[Timed]) -> Vec<(Instant, String)> {
+        (commands.iter())
+            .filter_map(|(time, command)| match command {
+                OutputCommand::WriteAll(WriteAll(bytes)) => {
+                    let line = String::from_utf8_lossy(bytes).trim_end().to_owned();
+                    Some((*time, line))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the lines left on the screen, which doesn't have the
+    /// status lines that were cleared.
+    fn screen(commands: &[Timed]) -> Vec<String> {
+        let mut lines = vec![];
+        for (_, command) in commands {
+            match command {
+                OutputCommand::MoveCursorUp(MoveCursorUp(n)) => {
+                    lines.truncate(lines.len().saturating_sub(usize::from(*n)));
+                }
+                OutputCommand::WriteAll(WriteAll(bytes)) => {
+                    lines.push(String::from_utf8_lossy(bytes).trim_end().to_owned());
+                }
+                _ => {}
+            }
+        }
+        lines
+    }
+
+    /// Returns the commands that left something on the screen: the
+    /// lines that were not cleared and their colors.
+    fn printed(commands: &[Timed]) -> Vec<&OutputCommand> {
+        let mut printed: Vec<&OutputCommand> = vec![];
+        for (_, command) in commands {
+            match command {
+                OutputCommand::MoveCursorUp(MoveCursorUp(n)) => {
+                    for _ in 0..*n {
+                        while let Some(command) = printed.pop() {
+                            if matches!(command, OutputCommand::WriteAll(_)) {
+                                break;
+                            }
+                        }
+                    }
+                }
+                OutputCommand::ClearLine(_) => {}
+                command => printed.push(command),
+            }
+        }
+        printed
+    }
+
+    /// Returns the command that writes the `line`.
+    fn line(line: &str) -> OutputCommand {
+        OutputCommand::WriteAll(WriteAll(format!("{line}\n").into_bytes()))
+    }
+
+    /// Returns the command that sets the `color`.
+    fn color(color: Option<Color>) -> OutputCommand {
+        OutputCommand::SetColor(SetColor(color))
+    }
+
+    /// Returns the time `millis` after the start.
+    fn at(millis: i64) -> Instant {
+        &Instant::default() + &Duration::milliseconds(millis)
+    }
+
+    fn run_script(line: &str, sts: ExitSts) -> Script {
+        Script::default()
+            .wait(Duration::milliseconds(600))
+            .out(line)
+            .wait(Duration::milliseconds(600))
+            .exit(sts)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_changed_runs() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![
+            run_script("a", ExitSts::Success),
+            run_script("a", ExitSts::Success),
+            run_script("b", ExitSts::Code(1)),
+        ]);
+        let commands = run(sys, Duration::seconds(1), Options::default()).await?;
+        assert_eq!(
+            screen(&commands),
+            vec![
+                "<O> start execution",
+                "<O> + cmd",
+                "a",
+                "<O> subprocess exited with success",
+                "<O> + cmd",
+                "b",
+                "<O> subprocess exited with code 1, changed: output, exit status",
+                // The engine ends when the fourth run yields nothing; the
+                // bar has one tick per 250ms of the 1.2s previous run:
+                "<O> running [>    ] [\\]",
+            ]
+        );
+        // The second run was unchanged:
+        let written = written(&commands);
+        assert!(
+            written
+                .iter()
+                .any(|(_, line)| line == "<O> unchanged 1, sleeping [-]")
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_narrow_terminal() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![run_script("a", ExitSts::Success); 2]);
+        sys.set_widths(vec![Some(40)]);
+        let commands = run(sys, Duration::seconds(1), Options::default()).await?;
+        let written = written(&commands);
+        // The status line has a progress bar in the second run, which
+        // we shorten to fit:
+        assert!(written.iter().any(|(_, line)| line.contains("running [>")));
+        assert!(written.iter().all(|(_, line)| line.chars().count() <= 40));
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_until_stable() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![run_script("a", ExitSts::Success); 5]);
+        let options = Options {
+            until_stable: Some(2),
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        assert_eq!(
+            screen(&commands),
+            vec![
+                "<O> start execution",
+                "<O> + cmd",
+                "a",
+                "<O> subprocess exited with success",
+                "<O> output stable for 2 runs",
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_user_quit() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![run_script("a", ExitSts::Success); 5]);
+        // The user quits during the second run, from 2.2s to 3.4s:
+        sys.set_user_events(vec![(Duration::milliseconds(2500), UserEvent::Quit)]);
+        let options = Options {
+            summary: Some(SummaryFormat::Text),
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        assert_eq!(
+            screen(&commands),
+            vec![
+                "<O> start execution",
+                "<O> + cmd",
+                "a",
+                "<O> subprocess exited with success",
+                "<O> user exit, graceful",
+                "<O> summary: 2 runs, 1 distinct outputs, over +3.400s",
+                "<O> exit statuses: success 2",
+                "<O> durations: min +1.200s avg +1.200s p95 +1.200s max +1.200s",
+            ]
+        );
+        Ok(())
+    }
+
+    fn stderr_runs() -> Vec<Script> {
+        vec![
+            Script::default().out("a").err("w1").exit(ExitSts::Success),
+            Script::default().out("a").err("w2").exit(ExitSts::Success),
+            Script::default().out("b").err("w2").exit(ExitSts::Success),
+        ]
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_compare_both() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(stderr_runs());
+        let commands = run(sys, Duration::seconds(1), Options::default()).await?;
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("a"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("a"),
+                &color(Some(Color::Red)),
+                &line("w2"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> + cmd"),
+                &line("b"),
+                &color(Some(Color::Red)),
+                &line("w2"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [\\]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_compare_stdout() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(stderr_runs());
+        let options = Options {
+            compare: Compare::Stdout,
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        // The second run only changed stderr:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("a"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("b"),
+                &color(Some(Color::Red)),
+                &line("w2"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [\\]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_compare_stderr() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(stderr_runs());
+        let options = Options {
+            compare: Compare::Stderr,
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        // The third run only changed stdout:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("a"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("a"),
+                &color(Some(Color::Red)),
+                &line("w2"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> unchanged 1, running [\\]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_json_stderr() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![
+            (Script::default().out(r#"{"a": 1}"#))
+                .err("w1")
+                .exit(ExitSts::Success),
+            (Script::default().out(r#"{"a": 2}"#))
+                .err("w1")
+                .exit(ExitSts::Success),
+        ]);
+        let options = Options {
+            mode: differ::Mode::Json,
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        // stderr is compared line by line, and printed before the
+        // JSON changes, which we only have when the run is done:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("added .a: 1"),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("changed .a: 1 → 2"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [\\]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_table_stderr() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![
+            (Script::default().out("NAME  VALUE").out("x     1"))
+                .err("w1")
+                .exit(ExitSts::Success),
+            (Script::default().out("NAME  VALUE").out("x     2"))
+                .err("w1")
+                .exit(ExitSts::Success),
+        ]);
+        let options = Options {
+            mode: differ::Mode::Table,
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("added x: x     1"),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("changed x: VALUE 1 → 2"),
+                &color(Some(Color::Red)),
+                &line("w1"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [/]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_delta() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![
+            run_script("rx 100 tx 7", ExitSts::Success),
+            run_script("rx 150 tx 7", ExitSts::Success),
+        ]);
+        let options = Options {
+            delta: true,
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        // The second run starts 2.2s after the first one:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("rx 100 tx 7"),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("rx 150 (+50, +22.7/s) tx 7"),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [>    ] [\\]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_filter() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![
+            Script::default()
+                .wait(Duration::milliseconds(600))
+                .out("a")
+                .err("w")
+                .wait(Duration::milliseconds(600))
+                .out("b")
+                .exit(ExitSts::Success),
+        ]);
+        let commands = run_filtered(
+            sys,
+            Duration::seconds(1),
+            Options::default(),
+            Some(Cmd::default()),
+        )
+        .await?;
+        // The stdout lines are held until the command exits, and then
+        // go through the filter; stderr is printed as it arrives:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &color(Some(Color::Red)),
+                &line("w"),
+                &color(None),
+                &line("a"),
+                &line("b"),
+                &line("<O> subprocess exited with success"),
+                &line("<O> running [>    ] [/]"),
+            ]
+        );
+        let lines = (written(&commands).into_iter())
+            .filter(|(_, line)| !line.starts_with("<O>"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                (at(600), "w".to_owned()),
+                (at(1200), "a".to_owned()),
+                (at(1200), "b".to_owned()),
+            ]
+        );
+        Ok(())
+    }
+
+    fn timed_runs() -> Vec<Script> {
+        ["a", "b"]
+            .into_iter()
+            .map(|line| {
+                Script::default()
+                    .wait(Duration::milliseconds(600))
+                    .out(line)
+                    .wait(Duration::milliseconds(600))
+                    .err("w")
+                    .exit(ExitSts::Success)
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_line_timestamps_absolute() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(timed_runs());
+        let options = Options {
+            line_timestamps: Some(LineTimestamps::Absolute),
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        // The second run starts at 2.2s:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("00:00:00.600 a"),
+                &color(Some(Color::Red)),
+                &line("00:00:01.200 w"),
+                &color(None),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("00:00:02.800 b"),
+                &color(Some(Color::Red)),
+                &line("00:00:03.400 w"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [>    ] [/]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_line_timestamps_relative() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(timed_runs());
+        let options = Options {
+            line_timestamps: Some(LineTimestamps::Relative),
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("+0.600s a"),
+                &color(Some(Color::Red)),
+                &line("+1.200s w"),
+                &color(None),
+                &line("<O> subprocess exited with success"),
+                &line("<O> + cmd"),
+                &line("+0.600s b"),
+                &color(Some(Color::Red)),
+                &line("+1.200s w"),
+                &color(None),
+                &line("<O> subprocess exited with success, changed: output"),
+                &line("<O> running [>    ] [/]"),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![
+            Script::default()
+                .out("a1")
+                .wait(Duration::milliseconds(1500))
+                .out("a2")
+                .exit(ExitSts::Success),
+            Script::default()
+                .out("b1")
+                .wait(Duration::milliseconds(200))
+                .out("b2")
+                .exit(ExitSts::Code(1)),
+        ]);
+        let options = Options {
+            max_concurrent: 2,
+            ..Default::default()
+        };
+        let commands = run(sys, Duration::seconds(1), options).await?;
+        // The runs overlap from 1s to 1.2s; we hold their lines and
+        // compare them in the order they finish:
+        assert_eq!(
+            printed(&commands),
+            vec![
+                &line("<O> start execution"),
+                &line("<O> + cmd"),
+                &line("b1"),
+                &line("b2"),
+                &line("<O> subprocess exited with code 1"),
+                &line("<O> + cmd"),
+                &line("a1"),
+                &line("a2"),
+                &line("<O> subprocess exited with success, changed: output, exit status"),
+                &line("<O> 1 in flight, running [>      ] [-]"),
+            ]
+        );
+        let lines = (written(&commands).into_iter())
+            .filter(|(_, line)| !line.starts_with("<O>"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                (at(1200), "b1".to_owned()),
+                (at(1200), "b2".to_owned()),
+                (at(1500), "a1".to_owned()),
+                (at(1500), "a2".to_owned()),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sleeping_status() -> Result<()> {
+        let mut sys = SysVirtual::default();
+        sys.set_runs(vec![run_script("a", ExitSts::Success); 2]);
+        let commands = run(sys, Duration::seconds(5), Options::default()).await?;
+        // The first run ends at 1.2s, so this is 2.5s into sleeping:
+        let status = (written(&commands).into_iter())
+            .rfind(|(time, _)| *time <= at(3700))
+            .map(|(_, line)| line);
+        assert_eq!(status.as_deref(), Some("<O> sleeping for 3s [|]"));
+        Ok(())
+    }
+}
@@ -94,3 +94,108 @@ fn test_until_failure_keeps_running_on_success() {
         "ogle should keep running when command succeeds with -e"
     );
 }
+
+#[test]
+fn test_until_expected() {
+    let path = std::env::temp_dir().join(format!("ogle-expect-{}", std::process::id()));
+    std::fs::write(&path, "expected line\n").unwrap();
+    let path = path.to_str().unwrap();
+    let exited = run_with_timeout(
+        &[
+            "--expect",
+            path,
+            "--until-expected",
+            "--",
+            "echo",
+            "expected line",
+        ],
+        Duration::from_secs(3),
+    );
+    assert!(exited, "ogle should exit when the output matches");
+    let exited = run_with_timeout(
+        &["--expect", path, "--until-expected", "--", "echo", "other"],
+        Duration::from_secs(3),
+    );
+    assert!(!exited, "ogle should keep running while the output differs");
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_until_stable() {
+    let output = ogle_bin()
+        .args(["-p", "0", "--until-stable", "2", "--", "echo", "stable"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("output stable for 2 runs"));
+}
+
+#[test]
+fn test_alert_exit() {
+    let output = ogle_bin()
+        .args([
+            "-p",
+            "0",
+            "--track",
+            r"free: (?<free_mb>\d+)",
+            "--alert",
+            "free_mb < 500",
+            "--alert-exit",
+            "--",
+            "echo",
+            "free: 100",
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("alert raised: free_mb < 500 (value 100)"));
+}
+
+#[test]
+fn test_summary_json() {
+    let output = ogle_bin()
+        .args(["-p", "0", "-z", "--summary", "json", "--", "true"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(r#""runs":1"#));
+    assert!(stdout.contains(r#""exit_statuses":{"success":1}"#));
+}
+
+#[test]
+fn test_asciicast() {
+    let dir = std::env::temp_dir().join(format!("ogle-asciicast-{}", std::process::id()));
+    let path = dir.join("demo.cast");
+    let output = ogle_bin()
+        .args(["-p", "0", "-z", "--asciicast"])
+        .arg(&path)
+        .args(["--", "echo", "hello"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let cast = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let mut lines = cast.lines();
+    assert!(lines.next().unwrap().contains(r#""version":2"#));
+    assert!(lines.any(|line| line.contains(r#""o","hello\n"#)));
+}
+
+#[test]
+fn test_record_invalid_option() {
+    let path = std::env::temp_dir().join(format!("ogle-record-{}", std::process::id()));
+    std::fs::write(&path, "previous recording\n").unwrap();
+    let output = ogle_bin()
+        .arg("--record")
+        .arg(&path)
+        .args(["--tolerance", "5x", "--", "true"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    // The existing recording is left alone:
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(contents, "previous recording\n");
+}